@@ -1,10 +1,20 @@
-use crate::touch::TriggerCorner;
+use crate::gesture::GestureBinding;
+use crate::ink::InkFormat;
+use crate::ink_limit::InkLimitAction;
+use crate::pen::DrawSpeed;
+use crate::pen_symbol::PenTrigger;
+use crate::svg_paths::HatchStyle;
+use crate::touch::{TriggerCorner, TriggerZone};
 use anyhow::Result;
 use figment::{
     providers::{Env, Format, Serialized, Toml},
     Figment,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Bitmaps are rendered at most this many times the screen resolution
+const MAX_RASTER_SCALE: u32 = 4;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
@@ -17,6 +27,15 @@ pub struct Config {
     pub no_submit: bool,
     pub no_draw: bool,
     pub no_svg: bool,
+    pub svg_raster: bool,
+    pub hatch_style: String,
+    pub hatch_spacing: f32,
+    pub skip_scanlines: bool,
+    pub raster_scale: u32,
+    pub max_ink_pixels: u64,
+    pub max_pen_events: u64,
+    pub ink_limit_action: String,
+    pub draw_speed: String,
     pub no_keyboard: bool,
     pub no_draw_progress: bool,
     pub input_png: Option<String>,
@@ -30,8 +49,54 @@ pub struct Config {
     pub web_search: bool,
     pub thinking: bool,
     pub thinking_tokens: u32,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub tool_choice: Option<String>,
     pub log_level: String,
     pub trigger_corner: String,
+    // Config-file only: several trigger zones, each with its own prompt; replaces trigger_corner
+    pub trigger_zones: Vec<TriggerZone>,
+    // Config-file only: gestures and the actions they are bound to
+    pub gestures: Vec<GestureBinding>,
+    // Config-file only: symbols drawn with the pen that trigger, each in its own zone
+    pub pen_triggers: Vec<PenTrigger>,
+    pub ink_context: Option<String>,
+    pub idle_trigger_secs: Option<u64>,
+    pub idle_prompt: Option<String>,
+    pub idle_min_interval_secs: u64,
+    pub no_palm_rejection: bool,
+    // Config-file only: contacts with a larger ABS_MT_TOUCH_MAJOR are palms (0 allows any size)
+    pub palm_touch_major: i32,
+    pub pen_device: Option<String>,
+    pub touch_device: Option<String>,
+    pub replay_touch_log: Option<String>,
+    pub record_trace: Option<String>,
+    pub tool_allowed_commands: Vec<String>,
+    pub tool_working_dir: Option<String>,
+    pub tool_keep_env: Vec<String>,
+    pub tool_timeout_secs: u64,
+    pub tool_max_output_bytes: usize,
+    pub tool_dry_run: bool,
+    // Config-file only: per-tool overrides of the limits above
+    pub tool_limits: HashMap<String, ToolLimits>,
+    pub router: bool,
+    pub router_engine: Option<String>,
+    pub router_model: Option<String>,
+    // Config-file only: the pages the router can tell apart and the prompt for each
+    pub router_routes: Vec<Route>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Route {
+    pub name: String,
+    pub description: String,
+    pub prompt: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ToolLimits {
+    pub timeout_secs: Option<u64>,
+    pub max_output_bytes: Option<usize>,
 }
 
 impl Default for Config {
@@ -45,6 +110,15 @@ impl Default for Config {
             no_submit: false,
             no_draw: false,
             no_svg: false,
+            svg_raster: false,
+            hatch_style: "parallel".to_string(),
+            hatch_spacing: 4.0,
+            skip_scanlines: false,
+            raster_scale: 1,
+            max_ink_pixels: 250_000,
+            max_pen_events: 500_000,
+            ink_limit_action: "outline".to_string(),
+            draw_speed: "normal".to_string(),
             no_keyboard: false,
             no_draw_progress: false,
             input_png: None,
@@ -58,8 +132,35 @@ impl Default for Config {
             web_search: false,
             thinking: false,
             thinking_tokens: 5000,
+            max_tokens: None,
+            temperature: None,
+            tool_choice: None,
             log_level: "info".to_string(),
             trigger_corner: "UR".to_string(),
+            trigger_zones: Vec::new(),
+            gestures: Vec::new(),
+            pen_triggers: Vec::new(),
+            ink_context: None,
+            idle_trigger_secs: None,
+            idle_prompt: None,
+            idle_min_interval_secs: 300,
+            no_palm_rejection: false,
+            palm_touch_major: 30,
+            pen_device: None,
+            touch_device: None,
+            replay_touch_log: None,
+            record_trace: None,
+            tool_allowed_commands: Vec::new(),
+            tool_working_dir: None,
+            tool_keep_env: vec!["PATH".to_string(), "HOME".to_string(), "LANG".to_string(), "TZ".to_string()],
+            tool_timeout_secs: 30,
+            tool_max_output_bytes: 64 * 1024,
+            tool_dry_run: false,
+            tool_limits: HashMap::new(),
+            router: false,
+            router_engine: None,
+            router_model: None,
+            router_routes: Vec::new(),
         }
     }
 }
@@ -102,10 +203,60 @@ impl Config {
         Ok(std::path::Path::new(&home).join(".ghostwriter.toml"))
     }
 
+    /// The configured trigger zones, or just the trigger corner if there are none
+    pub fn trigger_zones(&self) -> Vec<TriggerZone> {
+        if self.trigger_zones.is_empty() {
+            vec![TriggerZone::from_corner(&self.trigger_corner)]
+        } else {
+            self.trigger_zones.clone()
+        }
+    }
+
     /// Validate the configuration and return any errors
     pub fn validate(&self) -> Result<()> {
-        // Validate trigger corner
+        // Validate trigger corner, zones and pen triggers
         TriggerCorner::from_string(&self.trigger_corner)?;
+        for zone in &self.trigger_zones {
+            zone.validate()?;
+        }
+        for trigger in &self.pen_triggers {
+            trigger.validate()?;
+        }
+
+        // Validate SVG hatching
+        HatchStyle::from_string(&self.hatch_style)?;
+        if self.hatch_spacing < 1.0 {
+            return Err(anyhow::anyhow!("hatch_spacing must be at least 1"));
+        }
+        if !(1..=MAX_RASTER_SCALE).contains(&self.raster_scale) {
+            return Err(anyhow::anyhow!("raster_scale must be between 1 and {}", MAX_RASTER_SCALE));
+        }
+        InkLimitAction::from_string(&self.ink_limit_action)?;
+        DrawSpeed::from_string(&self.draw_speed)?;
+
+        if let Some(ink_context) = &self.ink_context {
+            InkFormat::from_string(ink_context)?;
+        }
+
+        // Validate the idle trigger
+        if self.idle_trigger_secs == Some(0) {
+            return Err(anyhow::anyhow!("idle_trigger_secs must be greater than 0"));
+        }
+        if self.idle_trigger_secs.is_some() && self.idle_prompt.is_none() {
+            return Err(anyhow::anyhow!("idle_trigger_secs needs an idle_prompt to run"));
+        }
+
+        if self.palm_touch_major < 0 {
+            return Err(anyhow::anyhow!("palm_touch_major must not be negative"));
+        }
+
+        // Validate gesture bindings
+        for (i, binding) in self.gestures.iter().enumerate() {
+            binding.validate()?;
+            if self.gestures[..i].iter().any(|other| other.gesture == binding.gesture) {
+                return Err(anyhow::anyhow!("Gesture {} is bound more than once", binding.gesture.name()));
+            }
+        }
 
         // Validate log level
         match self.log_level.as_str() {
@@ -118,6 +269,19 @@ impl Config {
             return Err(anyhow::anyhow!("thinking_tokens must be greater than 0"));
         }
 
+        // Validate external command limits
+        if self.tool_timeout_secs == 0 {
+            return Err(anyhow::anyhow!("tool_timeout_secs must be greater than 0"));
+        }
+        if self.tool_max_output_bytes == 0 {
+            return Err(anyhow::anyhow!("tool_max_output_bytes must be greater than 0"));
+        }
+
+        // Validate the router
+        if self.router && self.router_routes.is_empty() {
+            return Err(anyhow::anyhow!("router is enabled but no router_routes are configured"));
+        }
+
         Ok(())
     }
 }
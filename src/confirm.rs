@@ -0,0 +1,110 @@
+use anyhow::Result;
+use log::{info, warn};
+
+use crate::pen::Pen;
+use crate::touch::{Touch, TriggerZone};
+
+// Boxes are (x, y, width, height) in virtual coordinates, in the lower-right but clear of the
+// default trigger corner
+const YES_BOX: (i32, i32, i32, i32) = (548, 860, 100, 60);
+const NO_BOX: (i32, i32, i32, i32) = (660, 860, 100, 60);
+// Both boxes with a small margin, kept clear of trigger zones
+const CONFIRM_AREA: (i32, i32, i32, i32) = (YES_BOX.0 - 4, YES_BOX.1 - 4, NO_BOX.0 + NO_BOX.2 - YES_BOX.0 + 8, YES_BOX.3 + 8);
+
+// Letters are drawn as line segments in a 0..4 x 0..6 grid
+const LETTER_WIDTH: i32 = 4;
+const LETTER_HEIGHT: i32 = 6;
+
+type Segment = ((i32, i32), (i32, i32));
+
+fn letter_segments(letter: char) -> &'static [Segment] {
+    match letter {
+        'Y' => &[((0, 0), (2, 3)), ((4, 0), (2, 3)), ((2, 3), (2, 6))],
+        'E' => &[((4, 0), (0, 0)), ((0, 0), (0, 6)), ((0, 6), (4, 6)), ((0, 3), (3, 3))],
+        'S' => &[((4, 0), (0, 0)), ((0, 0), (0, 3)), ((0, 3), (4, 3)), ((4, 3), (4, 6)), ((4, 6), (0, 6))],
+        'N' => &[((0, 6), (0, 0)), ((0, 0), (4, 6)), ((4, 6), (4, 0))],
+        'O' => &[((0, 0), (4, 0)), ((4, 0), (4, 6)), ((4, 6), (0, 6)), ((0, 6), (0, 0))],
+        _ => &[],
+    }
+}
+
+fn box_segments((x, y, width, height): (i32, i32, i32, i32)) -> Vec<Segment> {
+    vec![
+        ((x, y), (x + width, y)),
+        ((x + width, y), (x + width, y + height)),
+        ((x + width, y + height), (x, y + height)),
+        ((x, y + height), (x, y)),
+    ]
+}
+
+/// Lay out a word centered in the box using the tiny line-segment alphabet above
+fn label_segments((x, y, width, height): (i32, i32, i32, i32), label: &str) -> Vec<Segment> {
+    let scale = 5;
+    let spacing = 2;
+    let letter_count = label.chars().count() as i32;
+    let label_width = letter_count * LETTER_WIDTH * scale + (letter_count - 1) * spacing * scale;
+    let origin_x = x + (width - label_width) / 2;
+    let origin_y = y + (height - LETTER_HEIGHT * scale) / 2;
+
+    let mut segments = Vec::new();
+    for (i, letter) in label.chars().enumerate() {
+        let letter_x = origin_x + i as i32 * (LETTER_WIDTH + spacing) * scale;
+        for &((x1, y1), (x2, y2)) in letter_segments(letter) {
+            segments.push(((letter_x + x1 * scale, origin_y + y1 * scale), (letter_x + x2 * scale, origin_y + y2 * scale)));
+        }
+    }
+    segments
+}
+
+/// Every line of the YES / NO boxes and their labels, in screen coordinates
+fn confirm_segments() -> Vec<Segment> {
+    let mut segments = box_segments(YES_BOX);
+    segments.extend(label_segments(YES_BOX, "YES"));
+    segments.extend(box_segments(NO_BOX));
+    segments.extend(label_segments(NO_BOX, "NO"));
+    segments
+}
+
+/// Rub out exactly the lines we drew, leaving anything the user wrote around them alone
+fn erase_segments(pen: &mut Pen, segments: &[Segment]) -> Result<()> {
+    for &((x1, y1), (x2, y2)) in segments {
+        pen.erase_stroke(&[(x1 as f32, y1 as f32), (x2 as f32, y2 as f32)])?;
+    }
+    Ok(())
+}
+
+fn overlaps((ax, ay, aw, ah): (i32, i32, i32, i32), (bx, by, bw, bh): (i32, i32, i32, i32)) -> bool {
+    ax < bx + bw && bx < ax + aw && ay < by + bh && by < ay + ah
+}
+
+/// Warn about trigger zones under the YES / NO boxes, where a tap meant for a zone could answer
+/// a confirmation instead
+pub fn check_trigger_zones(zones: &[TriggerZone]) {
+    for zone in zones {
+        if zone.bounds().is_some_and(|bounds| overlaps(bounds, CONFIRM_AREA)) {
+            warn!(
+                "Trigger zone {} overlaps the YES / NO confirmation boxes at {:?}; move it so taps on one aren't mistaken for the other",
+                zone.name(),
+                CONFIRM_AREA
+            );
+        }
+    }
+}
+
+/// Draw a YES / NO box pair, wait for the user to tap one of them, then erase what we drew
+pub fn confirm_on_screen(pen: &mut Pen, touch: &mut Touch) -> Result<bool> {
+    let segments = confirm_segments();
+    for &(p1, p2) in &segments {
+        pen.draw_line_screen(p1, p2)?;
+    }
+
+    // Only a tap made after the boxes are up counts, not one left over from while the model was
+    // thinking
+    touch.discard_pending();
+    info!("Waiting for a tap on YES or NO...");
+    let choice = touch.wait_for_tap_in(&[YES_BOX, NO_BOX]);
+    let erased = erase_segments(pen, &segments);
+    let choice = choice?;
+    erased?;
+    Ok(choice == 0)
+}
@@ -1,3 +1,6 @@
+use anyhow::Result;
+use evdev::{AbsoluteAxisCode, Device, KeyCode};
+use log::{debug, info};
 use std::path::Path;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -32,3 +35,91 @@ impl DeviceModel {
         }
     }
 }
+
+/// The kinds of input device ghostwriter reads from and writes to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputDeviceKind {
+    Pen,
+    Touch,
+}
+
+impl InputDeviceKind {
+    pub fn name(&self) -> &str {
+        match self {
+            InputDeviceKind::Pen => "pen",
+            InputDeviceKind::Touch => "touch",
+        }
+    }
+
+    /// The stylus digitizer reports the pen tool and its pressure; the touchscreen reports
+    /// multitouch slots and positions
+    fn matches(&self, device: &Device) -> bool {
+        let has_key = |key| device.supported_keys().is_some_and(|keys| keys.contains(key));
+        let has_axis = |axis| device.supported_absolute_axes().is_some_and(|axes| axes.contains(axis));
+        match self {
+            InputDeviceKind::Pen => has_key(KeyCode::BTN_TOOL_PEN) && has_axis(AbsoluteAxisCode::ABS_PRESSURE),
+            InputDeviceKind::Touch => {
+                has_axis(AbsoluteAxisCode::ABS_MT_SLOT)
+                    && has_axis(AbsoluteAxisCode::ABS_MT_POSITION_X)
+                    && has_axis(AbsoluteAxisCode::ABS_MT_POSITION_Y)
+                    && !has_key(KeyCode::BTN_TOOL_PEN)
+            }
+        }
+    }
+}
+
+/// The N in /dev/input/eventN, so event2 sorts before event10
+fn event_number(path: &Path) -> Option<u32> {
+    path.file_name()?.to_str()?.strip_prefix("event")?.parse().ok()
+}
+
+/// Find the device node for `kind`, using `override_path` (from `pen_device` / `touch_device`
+/// in the config) if given, or else the first `/dev/input/event*` with the right capabilities
+pub fn find_input_device(kind: InputDeviceKind, override_path: Option<&str>) -> Result<String> {
+    if let Some(path) = override_path {
+        let device = Device::open(path).map_err(|e| anyhow::anyhow!("Failed to open {} device {}: {}", kind.name(), path, e))?;
+        if !kind.matches(&device) {
+            return Err(anyhow::anyhow!(
+                "{} ({}) doesn't look like a {} device; check {}_device in the config",
+                path,
+                device.name().unwrap_or("unnamed"),
+                kind.name(),
+                kind.name()
+            ));
+        }
+        info!("Using configured {} device {}", kind.name(), path);
+        return Ok(path.to_string());
+    }
+
+    let mut devices: Vec<_> = evdev::enumerate().collect();
+    devices.sort_by_key(|(path, _)| (event_number(path), path.clone()));
+    for (path, device) in &devices {
+        debug!("Found input device {} ({})", path.display(), device.name().unwrap_or("unnamed"));
+    }
+    let (path, device) = devices.iter().find(|(_, device)| kind.matches(device)).ok_or_else(|| {
+        anyhow::anyhow!(
+            "No {} device found among the {} readable /dev/input/event* devices; set {}_device in the config",
+            kind.name(),
+            devices.len(),
+            kind.name()
+        )
+    })?;
+    info!("Found {} device {} ({})", kind.name(), path.display(), device.name().unwrap_or("unnamed"));
+    Ok(path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn event_devices_sort_numerically() {
+        let mut paths: Vec<PathBuf> = ["/dev/input/event10", "/dev/input/event2", "/dev/input/event1"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        paths.sort_by_key(|path| (event_number(path), path.clone()));
+        assert_eq!(paths, ["/dev/input/event1", "/dev/input/event2", "/dev/input/event10"].map(PathBuf::from));
+    }
+}
@@ -1,3 +1,4 @@
+use anyhow::Result;
 use rust_embed::Embed;
 
 #[derive(Embed)]
@@ -15,12 +16,27 @@ pub fn get_uinput_module_data(version: &str) -> Option<Vec<u8>> {
     AssetUtils::get(target_module_filename.as_str()).map(|asset| asset.data.to_vec())
 }
 
-pub fn load_config(filename: &str) -> String {
+/// Read a prompt or tool file, from disk if it exists there, otherwise from the embedded prompts
+pub fn load_config(filename: &str) -> Result<String> {
     log::debug!("Loading config from {}", filename);
 
     if std::path::Path::new(filename).exists() {
-        std::fs::read_to_string(filename).unwrap()
+        std::fs::read_to_string(filename).map_err(|e| anyhow::anyhow!("Failed to read '{}': {}", filename, e))
     } else {
-        std::str::from_utf8(AssetPrompts::get(filename).unwrap().data.as_ref()).unwrap().to_string()
+        let asset = AssetPrompts::get(filename).ok_or_else(|| anyhow::anyhow!("'{}' not found on disk or in the built-in prompts", filename))?;
+        std::str::from_utf8(asset.data.as_ref())
+            .map(|text| text.to_string())
+            .map_err(|e| anyhow::anyhow!("'{}' is not valid UTF-8: {}", filename, e))
+    }
+}
+
+/// Like load_config, but for binary files such as prompt-pack example images
+pub fn load_asset_bytes(filename: &str) -> Option<Vec<u8>> {
+    log::debug!("Loading asset from {}", filename);
+
+    if std::path::Path::new(filename).exists() {
+        std::fs::read(filename).ok()
+    } else {
+        AssetPrompts::get(filename).map(|asset| asset.data.to_vec())
     }
 }
@@ -0,0 +1,263 @@
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde_json::Value as json;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// A tool backed by an executable, as declared with `external_command` in a tool definition.
+///
+/// The model's arguments are written to the command's stdin as JSON and are also exposed as
+/// `GHOSTWRITER_ARG_<NAME>` environment variables. Whatever it prints to stdout is the tool result.
+///
+/// Commands are subject to the `tool_*` policy in the config: only allowlisted executables inside
+/// the working directory run, they start there with a scrubbed environment, and their runtime and
+/// output size are capped. This confines which executables run, not what they do once running;
+/// jailing the process's filesystem access is out of scope.
+pub struct ExternalCommand {
+    pub tool_name: String,
+    pub command: String,
+    pub timeout: Duration,
+    pub max_output_bytes: usize,
+    working_dir: PathBuf,
+    allowed_commands: Vec<String>,
+    keep_env: Vec<String>,
+    dry_run: bool,
+}
+
+impl ExternalCommand {
+    pub fn from_definition(definition: &json, config: &Config) -> Result<Self> {
+        let tool_name = definition["name"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Tool definition missing required 'name' field"))?
+            .to_string();
+        let command = definition["external_command"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Tool '{}' missing required 'external_command' field", tool_name))?
+            .to_string();
+
+        // The config sets the ceiling; a tool definition may only ask for less
+        let limits = config.tool_limits.get(&tool_name).cloned().unwrap_or_default();
+        let mut timeout_secs = limits.timeout_secs.unwrap_or(config.tool_timeout_secs);
+        if let Some(requested) = definition["timeout_secs"].as_u64() {
+            timeout_secs = timeout_secs.min(requested);
+        }
+        let max_output_bytes = limits.max_output_bytes.unwrap_or(config.tool_max_output_bytes);
+
+        let working_dir = match &config.tool_working_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => std::env::current_dir()?,
+        };
+
+        Ok(Self {
+            tool_name,
+            command,
+            timeout: Duration::from_secs(timeout_secs),
+            max_output_bytes,
+            working_dir,
+            allowed_commands: config.tool_allowed_commands.clone(),
+            keep_env: config.tool_keep_env.clone(),
+            dry_run: config.tool_dry_run,
+        })
+    }
+
+    /// Resolve the command inside the working directory and check it against the allowlist.
+    /// Absolute paths, and relative ones that end up outside the working directory (through `..`
+    /// or a symlink), are refused.
+    fn resolve_command(&self) -> Result<PathBuf> {
+        let working_dir = self
+            .working_dir
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Tool working directory {} is not usable: {}", self.working_dir.display(), e))?;
+
+        let resolve = |command: &str| -> Result<PathBuf> {
+            if Path::new(command).is_absolute() {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' must be relative to the tool working directory {}",
+                    command,
+                    working_dir.display()
+                ));
+            }
+            let resolved = working_dir
+                .join(command)
+                .canonicalize()
+                .map_err(|_| anyhow::anyhow!("Command '{}' not found in {}", command, working_dir.display()))?;
+            if !resolved.starts_with(&working_dir) {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' resolves to {}, outside the tool working directory {}",
+                    command,
+                    resolved.display(),
+                    working_dir.display()
+                ));
+            }
+            Ok(resolved)
+        };
+
+        let resolved = resolve(&self.command)?;
+
+        if self
+            .allowed_commands
+            .iter()
+            .filter_map(|allowed| resolve(allowed).ok())
+            .any(|allowed| allowed == resolved)
+        {
+            Ok(resolved)
+        } else {
+            Err(anyhow::anyhow!(
+                "Command '{}' is not in tool_allowed_commands; refusing to run it",
+                resolved.display()
+            ))
+        }
+    }
+
+    pub fn run(&self, arguments: &json) -> Result<String> {
+        let command = self.resolve_command()?;
+
+        if self.dry_run {
+            info!(
+                "Dry run, not running {} for tool {} with arguments {}",
+                command.display(),
+                self.tool_name,
+                arguments
+            );
+            return Ok(format!("Dry run: {} was not executed", self.command));
+        }
+
+        info!("Running external command '{}' for tool {}", command.display(), self.tool_name);
+        debug!("External command arguments: {}", arguments);
+
+        // Its own process group, so a timeout also takes down anything the command started
+        let mut child = Command::new(&command)
+            .process_group(0)
+            .current_dir(&self.working_dir)
+            .env_clear()
+            .envs(self.kept_env())
+            .envs(Self::argument_env(arguments))
+            .env("GHOSTWRITER_TOOL_NAME", &self.tool_name)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start '{}': {}", self.command, e))?;
+
+        // Drain the pipes in the background so a chatty command can't block on a full pipe
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        let max_output_bytes = self.max_output_bytes;
+        let stdout_reader = std::thread::spawn(move || read_capped(&mut stdout, max_output_bytes));
+        let stderr_reader = std::thread::spawn(move || read_capped(&mut stderr, max_output_bytes));
+
+        // Hand over the arguments and close stdin so the command sees EOF. This happens in the
+        // background too, so a command that never reads stdin still hits the timeout below.
+        if let Some(mut stdin) = child.stdin.take() {
+            let input = arguments.to_string();
+            std::thread::spawn(move || {
+                if let Err(e) = stdin.write_all(input.as_bytes()) {
+                    // Commands that don't care about stdin may exit before we finish writing
+                    debug!("Could not write arguments to stdin: {}", e);
+                }
+            });
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() > deadline {
+                kill_process_group(&child);
+                child.wait()?;
+                return Err(anyhow::anyhow!("'{}' timed out after {:?}", self.command, self.timeout));
+            }
+            sleep(Duration::from_millis(50));
+        };
+
+        // Anything the command left running in the background would hold our pipes open
+        kill_process_group(&child);
+        while !(stdout_reader.is_finished() && stderr_reader.is_finished()) {
+            if Instant::now() > deadline {
+                return Err(anyhow::anyhow!("Output of '{}' was still open after {:?}", self.command, self.timeout));
+            }
+            sleep(Duration::from_millis(50));
+        }
+
+        let (stdout, truncated) = stdout_reader.join().map_err(|_| anyhow::anyhow!("stdout reader panicked"))??;
+        let (stderr, _) = stderr_reader.join().map_err(|_| anyhow::anyhow!("stderr reader panicked"))??;
+        if truncated {
+            warn!("Output of '{}' was truncated to {} bytes", self.command, self.max_output_bytes);
+        }
+        let stderr = String::from_utf8_lossy(&stderr);
+        if !stderr.is_empty() {
+            debug!("External command stderr: {}", stderr);
+        }
+
+        if !status.success() {
+            return Err(anyhow::anyhow!("'{}' failed with {}: {}", self.command, status, stderr.trim()));
+        }
+
+        let output = String::from_utf8_lossy(&stdout).to_string();
+        debug!("External command output: {}", output);
+        Ok(output)
+    }
+
+    /// Only pass through the environment variables the config allows, so API keys stay put
+    fn kept_env(&self) -> Vec<(String, String)> {
+        self.keep_env
+            .iter()
+            .filter_map(|name| std::env::var(name).ok().map(|value| (name.clone(), value)))
+            .collect()
+    }
+
+    /// Top-level arguments become GHOSTWRITER_ARG_<NAME>; non-string values are passed as JSON
+    fn argument_env(arguments: &json) -> Vec<(String, String)> {
+        arguments
+            .as_object()
+            .map(|object| {
+                object
+                    .iter()
+                    .map(|(key, value)| {
+                        let name = format!("GHOSTWRITER_ARG_{}", key.to_uppercase());
+                        let value = match value {
+                            json::String(s) => s.clone(),
+                            other => other.to_string(),
+                        };
+                        (name, value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Kill the command and everything it started, which share its process group
+fn kill_process_group(child: &Child) {
+    // SAFETY: kill() only takes plain integers; a negative pid addresses the whole process group
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } != 0 {
+        // Nothing is left in the group once the command and its children have all exited
+        debug!("Could not kill process group {}: {}", child.id(), std::io::Error::last_os_error());
+    }
+}
+
+/// Read everything from the pipe but only keep the first `limit` bytes, reporting whether we dropped any
+fn read_capped(reader: &mut impl Read, limit: usize) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut chunk = [0u8; 4096];
+    loop {
+        let count = reader.read(&mut chunk)?;
+        if count == 0 {
+            break;
+        }
+        let room = limit.saturating_sub(kept.len());
+        if count > room {
+            truncated = true;
+        }
+        kept.extend_from_slice(&chunk[..count.min(room)]);
+    }
+    Ok((kept, truncated))
+}
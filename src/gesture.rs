@@ -0,0 +1,491 @@
+use anyhow::Result;
+use evdev::InputEvent;
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::UNIX_EPOCH;
+
+use crate::device::DeviceModel;
+use crate::touch::input_to_virtual;
+
+// Event codes
+const EV_SYN: u16 = 0;
+const EV_ABS: u16 = 3;
+const SYN_REPORT: u16 = 0;
+const ABS_MT_SLOT: u16 = 47;
+const ABS_MT_TOUCH_MAJOR: u16 = 48;
+const ABS_MT_POSITION_X: u16 = 53;
+const ABS_MT_POSITION_Y: u16 = 54;
+const ABS_MT_TRACKING_ID: u16 = 57;
+
+// Thresholds, in seconds and virtual pixels
+const TAP_SLOP: f64 = 20.0;
+const TAP_MAX_SECS: f64 = 0.5;
+const LONG_PRESS_SECS: f64 = 0.8;
+const DOUBLE_TAP_GAP_SECS: f64 = 0.3;
+const DOUBLE_TAP_SLOP: f64 = 40.0;
+const MULTI_FINGER_TAP_MAX_SECS: f64 = 0.5;
+const EDGE_MARGIN: i32 = 40;
+const SWIPE_MIN_DISTANCE: i32 = 150;
+
+// Output dimensions remain the same for both devices
+const VIRTUAL_WIDTH: i32 = 768;
+const VIRTUAL_HEIGHT: i32 = 1024;
+
+/// A raw touchscreen event, either read from the device or replayed from an evtest log
+#[derive(Debug, Clone, Copy)]
+pub struct TouchEvent {
+    /// Seconds since the epoch
+    pub time: f64,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl TouchEvent {
+    pub fn from_input_event(event: &InputEvent) -> Self {
+        let time = event.timestamp().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        Self {
+            time,
+            event_type: event.event_type().0,
+            code: event.code(),
+            value: event.value(),
+        }
+    }
+
+    /// Parse a line of `evtest` output, such as
+    /// `Event: time 1700000000.123456, type 3 (EV_ABS), code 57 (ABS_MT_TRACKING_ID), value 5`
+    fn from_evtest_line(line: &str) -> Option<Self> {
+        let rest = line.trim().strip_prefix("Event: time ")?;
+        let (time, rest) = rest.split_once(',')?;
+        let time = time.trim().parse::<f64>().ok()?;
+
+        if rest.contains("SYN_REPORT") && !rest.contains("type") {
+            return Some(Self {
+                time,
+                event_type: EV_SYN,
+                code: SYN_REPORT,
+                value: 0,
+            });
+        }
+
+        let mut event_type = None;
+        let mut code = None;
+        let mut value = None;
+        for field in rest.split(',') {
+            let mut words = field.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("type"), Some(number)) => event_type = number.parse().ok(),
+                (Some("code"), Some(number)) => code = number.parse().ok(),
+                (Some("value"), Some(number)) => value = number.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(Self {
+            time,
+            event_type: event_type?,
+            code: code?,
+            value: value?,
+        })
+    }
+}
+
+/// Load the events of a touchscreen recorded with `evtest /dev/input/event2 > touch.log`
+pub fn read_evtest_log(path: &str) -> Result<VecDeque<TouchEvent>> {
+    let content = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read touch log '{}': {}", path, e))?;
+    let events: VecDeque<TouchEvent> = content.lines().filter_map(TouchEvent::from_evtest_line).collect();
+    if events.is_empty() {
+        return Err(anyhow::anyhow!("No evtest events found in touch log '{}'", path));
+    }
+    debug!("Loaded {} touch events from {}", events.len(), path);
+    Ok(events)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum GestureKind {
+    Tap,
+    DoubleTap,
+    LongPress,
+    TwoFingerTap,
+    ThreeFingerTap,
+    SwipeFromLeft,
+    SwipeFromRight,
+    SwipeFromTop,
+    SwipeFromBottom,
+}
+
+impl GestureKind {
+    pub fn name(&self) -> &str {
+        match self {
+            GestureKind::Tap => "tap",
+            GestureKind::DoubleTap => "double-tap",
+            GestureKind::LongPress => "long-press",
+            GestureKind::TwoFingerTap => "two-finger-tap",
+            GestureKind::ThreeFingerTap => "three-finger-tap",
+            GestureKind::SwipeFromLeft => "swipe-from-left",
+            GestureKind::SwipeFromRight => "swipe-from-right",
+            GestureKind::SwipeFromTop => "swipe-from-top",
+            GestureKind::SwipeFromBottom => "swipe-from-bottom",
+        }
+    }
+}
+
+/// A recognized gesture and where it started, in virtual coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct Gesture {
+    pub kind: GestureKind,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GestureAction {
+    /// Run ghostwriter, optionally with the binding's prompt
+    Trigger,
+    /// Stop what ghostwriter is doing
+    Cancel,
+    /// Send Ctrl+Z to undo the last edit
+    Undo,
+    /// Use the binding's prompt for the following triggers
+    SwitchPrompt,
+}
+
+/// Binds a gesture to an action, from `[[gestures]]` in the config file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GestureBinding {
+    pub gesture: GestureKind,
+    pub action: GestureAction,
+    pub prompt: Option<String>,
+}
+
+impl GestureBinding {
+    pub fn validate(&self) -> Result<()> {
+        if self.gesture == GestureKind::Tap {
+            return Err(anyhow::anyhow!("A plain tap can't be bound to a gesture action; use trigger_zones instead"));
+        }
+        if self.action == GestureAction::SwitchPrompt && self.prompt.is_none() {
+            return Err(anyhow::anyhow!("Gesture {} uses switch-prompt but has no prompt", self.gesture.name()));
+        }
+        Ok(())
+    }
+}
+
+/// What a multitouch slot has seen since the last SYN_REPORT
+#[derive(Default)]
+struct Slot {
+    raw_x: i32,
+    raw_y: i32,
+    touch_major: i32,
+    active: bool,
+    went_down: bool,
+    went_up: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Contact {
+    start: (i32, i32),
+    last: (i32, i32),
+    start_time: f64,
+    end_time: f64,
+    // Largest contact size seen, in raw ABS_MT_TOUCH_MAJOR units
+    touch_major: i32,
+}
+
+impl Contact {
+    fn distance(&self) -> f64 {
+        let dx = (self.last.0 - self.start.0) as f64;
+        let dy = (self.last.1 - self.start.1) as f64;
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+/// Turns raw multitouch events into gestures.
+///
+/// Contacts are tracked per `ABS_MT_SLOT` and applied on each `SYN_REPORT`. A gesture is
+/// classified once the last finger is lifted, from how many fingers took part, how long they
+/// were down and how far they moved. If any contact was bigger than a fingertip, the whole
+/// gesture is taken to be a palm and ignored.
+pub struct GestureRecognizer {
+    device_model: DeviceModel,
+    max_touch_major: Option<i32>,
+    slot: usize,
+    slots: HashMap<usize, Slot>,
+    contacts: HashMap<usize, Contact>,
+    finished: Vec<Contact>,
+    // A single tap and when it was released, held back until it can't become a double-tap
+    pending_tap: Option<(f64, Gesture)>,
+}
+
+impl GestureRecognizer {
+    pub fn new(device_model: DeviceModel) -> Self {
+        Self {
+            device_model,
+            max_touch_major: None,
+            slot: 0,
+            slots: HashMap::new(),
+            contacts: HashMap::new(),
+            finished: Vec::new(),
+            pending_tap: None,
+        }
+    }
+
+    /// Ignore gestures with a contact larger than `max_touch_major`, in raw device units
+    pub fn set_max_touch_major(&mut self, max_touch_major: Option<i32>) {
+        self.max_touch_major = max_touch_major;
+    }
+
+    /// True when no finger is on the screen
+    pub fn is_idle(&self) -> bool {
+        self.contacts.is_empty()
+    }
+
+    /// True while a tap is held back waiting to see if a second one makes it a double-tap
+    pub fn has_pending_tap(&self) -> bool {
+        self.pending_tap.is_some()
+    }
+
+    /// The held-back tap, once it's `now` (seconds since the epoch) and too late for a second tap
+    /// to make it a double-tap
+    pub fn expire_pending(&mut self, now: f64) -> Option<Gesture> {
+        let (released, _) = self.pending_tap?;
+        let too_late = |time: f64| time - released > DOUBLE_TAP_GAP_SECS;
+        // A finger that landed in time could still be the second tap
+        if !too_late(now) || self.contacts.values().any(|contact| !too_late(contact.start_time)) {
+            return None;
+        }
+        let (_, tap) = self.pending_tap.take()?;
+        debug!("Recognized tap at ({}, {})", tap.x, tap.y);
+        Some(tap)
+    }
+
+    /// Forget the held-back tap, such as when the pen was near the screen
+    pub fn discard_pending(&mut self) {
+        self.pending_tap = None;
+    }
+
+    /// Feed one event, returning a gesture if this event completed one. A single tap isn't
+    /// returned until `expire_pending` says it's not part of a double-tap.
+    pub fn process(&mut self, event: &TouchEvent) -> Option<Gesture> {
+        match (event.event_type, event.code) {
+            (EV_ABS, ABS_MT_SLOT) => self.slot = event.value.max(0) as usize,
+            (EV_ABS, ABS_MT_TRACKING_ID) => {
+                let slot = self.slots.entry(self.slot).or_default();
+                if event.value == -1 {
+                    slot.went_up = true;
+                } else {
+                    slot.went_down = true;
+                }
+            }
+            (EV_ABS, ABS_MT_POSITION_X) => self.slots.entry(self.slot).or_default().raw_x = event.value,
+            (EV_ABS, ABS_MT_POSITION_Y) => self.slots.entry(self.slot).or_default().raw_y = event.value,
+            (EV_ABS, ABS_MT_TOUCH_MAJOR) => self.slots.entry(self.slot).or_default().touch_major = event.value,
+            (EV_SYN, SYN_REPORT) => return self.sync(event.time),
+            _ => {}
+        }
+        None
+    }
+
+    fn sync(&mut self, time: f64) -> Option<Gesture> {
+        for (&index, slot) in self.slots.iter_mut() {
+            let position = input_to_virtual(self.device_model, (slot.raw_x, slot.raw_y));
+            if slot.went_down {
+                slot.active = true;
+                self.contacts.insert(
+                    index,
+                    Contact {
+                        start: position,
+                        last: position,
+                        start_time: time,
+                        end_time: time,
+                        touch_major: slot.touch_major,
+                    },
+                );
+            } else if slot.active {
+                if let Some(contact) = self.contacts.get_mut(&index) {
+                    contact.last = position;
+                    contact.touch_major = contact.touch_major.max(slot.touch_major);
+                }
+            }
+            if slot.went_up {
+                slot.active = false;
+                if let Some(mut contact) = self.contacts.remove(&index) {
+                    contact.end_time = time;
+                    trace!("Contact in slot {} lifted at {:?}", index, contact.last);
+                    self.finished.push(contact);
+                }
+            }
+            slot.went_down = false;
+            slot.went_up = false;
+        }
+
+        if self.contacts.is_empty() && !self.finished.is_empty() {
+            let contacts = std::mem::take(&mut self.finished);
+            return self.classify(&contacts);
+        }
+        None
+    }
+
+    fn classify(&mut self, contacts: &[Contact]) -> Option<Gesture> {
+        if let Some(max_touch_major) = self.max_touch_major {
+            if let Some(palm) = contacts.iter().find(|c| c.touch_major > max_touch_major) {
+                debug!("Ignoring a palm at {:?} (touch major {} > {})", palm.start, palm.touch_major, max_touch_major);
+                return None;
+            }
+        }
+
+        // Multi-finger gestures are reported where the first finger landed
+        let first = *contacts.iter().min_by(|a, b| a.start_time.total_cmp(&b.start_time))?;
+        let start_time = first.start_time;
+        let end_time = contacts.iter().map(|c| c.end_time).fold(f64::MIN, f64::max);
+        let duration = end_time - start_time;
+        let stationary = contacts.iter().all(|c| c.distance() <= TAP_SLOP);
+        let gesture = |kind| {
+            Some(Gesture {
+                kind,
+                x: first.start.0,
+                y: first.start.1,
+            })
+        };
+
+        let result = match contacts.len() {
+            3 if stationary && duration <= MULTI_FINGER_TAP_MAX_SECS => gesture(GestureKind::ThreeFingerTap),
+            2 if stationary && duration <= MULTI_FINGER_TAP_MAX_SECS => gesture(GestureKind::TwoFingerTap),
+            1 if stationary && duration >= LONG_PRESS_SECS => gesture(GestureKind::LongPress),
+            1 if stationary && duration <= TAP_MAX_SECS => {
+                let is_double = self.pending_tap.is_some_and(|(released, tap)| {
+                    let distance = (((first.start.0 - tap.x).pow(2) + (first.start.1 - tap.y).pow(2)) as f64).sqrt();
+                    first.start_time - released <= DOUBLE_TAP_GAP_SECS && distance <= DOUBLE_TAP_SLOP
+                });
+                if is_double {
+                    self.pending_tap = None;
+                    gesture(GestureKind::DoubleTap)
+                } else {
+                    // A tap somewhere else can't finish the earlier one, so that one is just a tap
+                    let earlier = self.pending_tap.take().map(|(_, tap)| tap);
+                    self.pending_tap = gesture(GestureKind::Tap).map(|tap| (first.end_time, tap));
+                    trace!("Holding a tap at {:?} for a possible double-tap", first.start);
+                    if let Some(tap) = &earlier {
+                        debug!("Recognized tap at ({}, {})", tap.x, tap.y);
+                    }
+                    return earlier;
+                }
+            }
+            1 => Self::edge_swipe(&first).and_then(gesture),
+            _ => None,
+        };
+
+        if let Some(gesture) = &result {
+            debug!(
+                "Recognized {} at ({}, {}) from {} contact(s) over {:.2}s",
+                gesture.kind.name(),
+                gesture.x,
+                gesture.y,
+                contacts.len(),
+                duration
+            );
+        }
+        result
+    }
+
+    /// A single finger that starts at an edge and travels mostly straight inward
+    fn edge_swipe(contact: &Contact) -> Option<GestureKind> {
+        let (x, y) = contact.start;
+        let dx = contact.last.0 - x;
+        let dy = contact.last.1 - y;
+        let horizontal = dx.abs() > 2 * dy.abs();
+        let vertical = dy.abs() > 2 * dx.abs();
+
+        if horizontal && x <= EDGE_MARGIN && dx >= SWIPE_MIN_DISTANCE {
+            Some(GestureKind::SwipeFromLeft)
+        } else if horizontal && x >= VIRTUAL_WIDTH - EDGE_MARGIN && -dx >= SWIPE_MIN_DISTANCE {
+            Some(GestureKind::SwipeFromRight)
+        } else if vertical && y <= EDGE_MARGIN && dy >= SWIPE_MIN_DISTANCE {
+            Some(GestureKind::SwipeFromTop)
+        } else if vertical && y >= VIRTUAL_HEIGHT - EDGE_MARGIN && -dy >= SWIPE_MIN_DISTANCE {
+            Some(GestureKind::SwipeFromBottom)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/gestures/{}.log", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// Replay a recorded evtest log through the recognizer the way the touch listener does
+    fn recognize(recognizer: &mut GestureRecognizer, name: &str) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+        for event in read_evtest_log(&fixture(name)).unwrap() {
+            gestures.extend(recognizer.expire_pending(event.time));
+            gestures.extend(recognizer.process(&event));
+        }
+        gestures.extend(recognizer.expire_pending(f64::INFINITY));
+        gestures
+    }
+
+    fn kinds(name: &str) -> Vec<GestureKind> {
+        let mut recognizer = GestureRecognizer::new(DeviceModel::Remarkable2);
+        recognize(&mut recognizer, name).iter().map(|gesture| gesture.kind).collect()
+    }
+
+    #[test]
+    fn tap() {
+        let mut recognizer = GestureRecognizer::new(DeviceModel::Remarkable2);
+        let gestures = recognize(&mut recognizer, "tap");
+        assert_eq!(gestures.len(), 1);
+        assert_eq!(gestures[0].kind, GestureKind::Tap);
+        assert_eq!((gestures[0].x, gestures[0].y), (384, 512));
+    }
+
+    #[test]
+    fn tap_waits_for_the_double_tap_window() {
+        let mut recognizer = GestureRecognizer::new(DeviceModel::Remarkable2);
+        let events = read_evtest_log(&fixture("tap")).unwrap();
+        let released = events.back().unwrap().time;
+        assert!(events.iter().all(|event| recognizer.process(event).is_none()));
+        assert!(recognizer.has_pending_tap());
+        assert!(recognizer.expire_pending(released + DOUBLE_TAP_GAP_SECS / 2.0).is_none());
+        let tap = recognizer.expire_pending(released + DOUBLE_TAP_GAP_SECS * 2.0).unwrap();
+        assert_eq!(tap.kind, GestureKind::Tap);
+        assert!(!recognizer.has_pending_tap());
+    }
+
+    #[test]
+    fn double_tap() {
+        assert_eq!(kinds("double_tap"), vec![GestureKind::DoubleTap]);
+    }
+
+    #[test]
+    fn taps_far_apart_are_separate_taps() {
+        assert_eq!(kinds("two_taps_apart"), vec![GestureKind::Tap, GestureKind::Tap]);
+    }
+
+    #[test]
+    fn long_press() {
+        assert_eq!(kinds("long_press"), vec![GestureKind::LongPress]);
+    }
+
+    #[test]
+    fn two_finger_tap() {
+        assert_eq!(kinds("two_finger_tap"), vec![GestureKind::TwoFingerTap]);
+    }
+
+    #[test]
+    fn swipe_from_right() {
+        assert_eq!(kinds("swipe_from_right"), vec![GestureKind::SwipeFromRight]);
+    }
+
+    #[test]
+    fn palm_is_ignored() {
+        let mut recognizer = GestureRecognizer::new(DeviceModel::Remarkable2);
+        recognizer.set_max_touch_major(Some(4));
+        assert!(recognize(&mut recognizer, "tap").is_empty());
+    }
+}
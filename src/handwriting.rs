@@ -0,0 +1,454 @@
+use anyhow::Result;
+use log::debug;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::svg_paths::Polyline;
+
+// Glyphs are drawn on a grid where capitals run from y=0 to the baseline at y=10, lowercase
+// letters from y=4, and descenders reach down to y=13
+const CAP_HEIGHT: f32 = 10.0;
+const BASELINE: f32 = 10.0;
+const LINE_HEIGHT: f32 = 17.0;
+// Gap added after every glyph, on top of its own width
+const LETTER_SPACING: f32 = 1.0;
+// Points per full turn of an arc
+const ARC_STEPS: f32 = 32.0;
+// Limits on what the model may ask for, in virtual pixels and degrees
+const MIN_SIZE: f32 = 6.0;
+const MAX_SIZE: f32 = 200.0;
+const MAX_SLANT: f32 = 45.0;
+// Output dimensions remain the same for both devices
+const VIRTUAL_WIDTH: f32 = 768.0;
+const VIRTUAL_HEIGHT: f32 = 1024.0;
+
+/// A single-stroke font in the spirit of the Hershey fonts: each glyph is its advance width
+/// and a path where `M x y` lifts the pen and moves, `L x y` draws a line, and
+/// `A cx cy rx ry from to` draws an elliptical arc, continuing the stroke, that sweeps from one
+/// angle to the other in degrees (0 is to the right and 90 is down, so 0 to 360 goes all the
+/// way round clockwise).
+const GLYPHS: &[(char, f32, &str)] = &[
+    (' ', 4.0, ""),
+    ('!', 2.5, "M 1 0 L 1 7 M 1 9.8 L 1 10"),
+    ('"', 4.0, "M 1 0 L 1 2.5 M 2.5 0 L 2.5 2.5"),
+    ('#', 7.0, "M 2 1 L 1.5 10 M 5 1 L 4.5 10 M 0.5 4 L 6 4 M 0.5 7 L 6 7"),
+    ('$', 7.5, "A 3.5 3 2.5 2 -20 -270 A 3.5 7 2.5 2 -90 160 M 3.5 -0.5 L 3.5 10.5"),
+    ('%', 7.5, "A 1.5 2 1.2 1.7 0 360 M 6 0 L 1 10 M 6.7 8 A 5.5 8 1.2 1.7 0 360"),
+    ('&', 7.5, "M 6.5 10 L 1.8 3.6 A 3 2 1.5 1.8 150 390 L 1.2 6.6 A 2.8 8 1.8 2 210 420 L 6.5 6"),
+    ('\'', 2.5, "M 1 0 L 1 2.5"),
+    ('(', 3.5, "A 4 5 3 6 -130 -230"),
+    (')', 3.5, "A 0 5 3 6 -50 50"),
+    ('*', 6.5, "M 3 2 L 3 8 M 0.4 3.5 L 5.6 6.5 M 0.4 6.5 L 5.6 3.5"),
+    ('+', 7.5, "M 0.5 6 L 6.5 6 M 3.5 3 L 3.5 9"),
+    (',', 2.5, "M 1.2 9.5 L 1.2 10 L 0.5 11.5"),
+    ('-', 5.5, "M 0.5 6 L 4.5 6"),
+    ('.', 2.5, "M 1 9.8 L 1 10"),
+    ('/', 5.5, "M 5 0 L 0 11"),
+    ('0', 7.0, "A 3 5 2.5 5 0 360"),
+    ('1', 5.0, "M 1 2 L 3 0 L 3 10"),
+    ('2', 7.0, "A 3 2.8 2.5 2.5 -165 20 L 0.5 10 L 6 10"),
+    ('3', 7.0, "A 3 2.5 2.3 2.5 -160 90 A 3 7.5 2.6 2.5 -90 160"),
+    ('4', 7.0, "M 4.5 10 L 4.5 0 L 0 7 L 6.5 7"),
+    ('5', 7.0, "M 5.5 0 L 1 0 L 0.5 4.5 A 3 6.8 2.7 3.2 -120 150"),
+    ('6', 7.0, "A 3.5 5 3 5 -70 -180 L 0.5 7 A 3 7 2.5 3 180 540"),
+    ('7', 7.0, "M 0.5 0 L 6 0 L 2 10"),
+    ('8', 7.0, "A 3 2.5 2.2 2.5 90 450 A 3 7.5 2.6 2.5 -90 270"),
+    ('9', 7.0, "A 3 3 2.5 3 0 360 L 5.5 6.5 A 3 6.5 2.5 3.5 0 120"),
+    (':', 2.5, "M 1 4.8 L 1 5.2 M 1 9.8 L 1 10.2"),
+    (';', 2.5, "M 1.2 4.8 L 1.2 5.2 M 1.2 9.5 L 1.2 10 L 0.5 11.5"),
+    ('<', 7.0, "M 6 3 L 0.5 6 L 6 9"),
+    ('=', 7.0, "M 0.5 4.5 L 6 4.5 M 0.5 7.5 L 6 7.5"),
+    ('>', 7.0, "M 0.5 3 L 6 6 L 0.5 9"),
+    ('?', 6.5, "A 3 2.5 2.5 2.5 -160 90 L 3 7 M 3 9.8 L 3 10"),
+    ('@', 9.0, "A 4 6 1.5 2 0 360 M 5.5 4 L 5.5 7.5 A 6.5 7.5 1 1 180 0 A 4 6 3.5 4.5 0 -280"),
+    ('A', 8.0, "M 0 10 L 3.5 0 L 7 10 M 1.3 6.5 L 5.7 6.5"),
+    (
+        'B',
+        8.0,
+        "M 0.5 10 L 0.5 0 L 4 0 A 4 2.5 2 2.5 -90 90 L 0.5 5 M 4 5 A 4 7.5 2.5 2.5 -90 90 L 0.5 10",
+    ),
+    ('C', 8.0, "A 4 5 3.5 5 -40 -320"),
+    ('D', 8.0, "M 0.5 0 L 0.5 10 L 3 10 A 3 5 4 5 90 -90 L 0.5 0"),
+    ('E', 7.0, "M 6 0 L 0.5 0 L 0.5 10 L 6 10 M 0.5 5 L 4.5 5"),
+    ('F', 7.0, "M 6 0 L 0.5 0 L 0.5 10 M 0.5 5 L 4.5 5"),
+    ('G', 8.5, "A 4 5 3.5 5 -40 -360 L 4.5 5"),
+    ('H', 8.0, "M 0.5 0 L 0.5 10 M 6.5 0 L 6.5 10 M 0.5 5 L 6.5 5"),
+    ('I', 4.0, "M 1.5 0 L 1.5 10 M 0 0 L 3 0 M 0 10 L 3 10"),
+    ('J', 6.5, "M 5 0 L 5 7 A 2.75 7 2.25 3 0 180"),
+    ('K', 7.5, "M 0.5 0 L 0.5 10 M 6 0 L 0.5 6 M 2.3 4 L 6.5 10"),
+    ('L', 6.5, "M 0.5 0 L 0.5 10 L 6 10"),
+    ('M', 9.0, "M 0.5 10 L 0.5 0 L 4 7 L 7.5 0 L 7.5 10"),
+    ('N', 8.0, "M 0.5 10 L 0.5 0 L 6.5 10 L 6.5 0"),
+    ('O', 9.0, "A 4 5 3.5 5 0 360"),
+    ('P', 7.5, "M 0.5 10 L 0.5 0 L 4 0 A 4 2.75 2.5 2.75 -90 90 L 0.5 5.5"),
+    ('Q', 9.0, "A 4 5 3.5 5 0 360 M 4.5 7.5 L 7.5 10.5"),
+    ('R', 7.5, "M 0.5 10 L 0.5 0 L 4 0 A 4 2.75 2.5 2.75 -90 90 L 0.5 5.5 M 3.5 5.5 L 6.5 10"),
+    ('S', 7.5, "A 3.5 2.5 2.8 2.5 -20 -270 A 3.5 7.5 3 2.5 -90 160"),
+    ('T', 7.5, "M 0 0 L 7 0 M 3.5 0 L 3.5 10"),
+    ('U', 8.0, "M 0.5 0 L 0.5 6.5 A 3.5 6.5 3 3.5 180 0 L 6.5 0"),
+    ('V', 7.5, "M 0 0 L 3.5 10 L 7 0"),
+    ('W', 10.5, "M 0 0 L 2.5 10 L 5 2 L 7.5 10 L 10 0"),
+    ('X', 7.5, "M 0.5 0 L 6.5 10 M 6.5 0 L 0.5 10"),
+    ('Y', 7.5, "M 0 0 L 3.5 5 L 7 0 M 3.5 5 L 3.5 10"),
+    ('Z', 7.5, "M 0.5 0 L 6.5 0 L 0.5 10 L 6.5 10"),
+    ('[', 4.0, "M 3 -0.5 L 1 -0.5 L 1 11 L 3 11"),
+    ('\\', 5.5, "M 0 0 L 5 11"),
+    (']', 4.0, "M 1 -0.5 L 3 -0.5 L 3 11 L 1 11"),
+    ('^', 6.5, "M 0.5 3 L 3 0 L 5.5 3"),
+    ('_', 6.0, "M 0 11 L 6 11"),
+    ('`', 3.0, "M 1 0 L 2 1.5"),
+    ('a', 7.0, "A 3 7 2.5 3 0 360 M 5.5 4 L 5.5 10"),
+    ('b', 7.0, "M 0.5 0 L 0.5 10 M 0.5 7 A 3 7 2.5 3 180 540"),
+    ('c', 6.5, "A 3 7 2.5 3 -40 -320"),
+    ('d', 7.0, "M 5.5 0 L 5.5 10 M 5.5 7 A 3 7 2.5 3 0 360"),
+    ('e', 7.0, "M 0.5 7 L 5.5 7 A 3 7 2.5 3 0 -320"),
+    ('f', 5.0, "A 3.5 1.5 1.5 1.5 -20 -180 L 2 10 M 0.5 4 L 4 4"),
+    ('g', 7.0, "A 3 7 2.5 3 0 360 M 5.5 4 L 5.5 11 A 3 11 2.5 2 0 150"),
+    ('h', 7.0, "M 0.5 0 L 0.5 10 M 0.5 6.5 A 3 6.5 2.5 2.5 180 360 L 5.5 10"),
+    ('i', 3.0, "M 1 4 L 1 10 M 1 1.8 L 1 2.2"),
+    ('j', 4.0, "M 2.5 4 L 2.5 11 A 0.5 11 2 2 0 150 M 2.5 1.8 L 2.5 2.2"),
+    ('k', 6.5, "M 0.5 0 L 0.5 10 M 5 4 L 0.5 8 M 2.2 6.5 L 5.5 10"),
+    ('l', 3.0, "M 1 0 L 1 10"),
+    (
+        'm',
+        9.0,
+        "M 0.5 4 L 0.5 10 M 0.5 6 A 2.25 6 1.75 2 180 360 L 4 10 M 4 6 A 5.75 6 1.75 2 180 360 L 7.5 10",
+    ),
+    ('n', 7.0, "M 0.5 4 L 0.5 10 M 0.5 6.5 A 3 6.5 2.5 2.5 180 360 L 5.5 10"),
+    ('o', 7.0, "A 3 7 2.5 3 0 360"),
+    ('p', 7.0, "M 0.5 4 L 0.5 13 M 0.5 7 A 3 7 2.5 3 180 540"),
+    ('q', 7.0, "M 5.5 4 L 5.5 13 M 5.5 7 A 3 7 2.5 3 0 360"),
+    ('r', 5.0, "M 0.5 4 L 0.5 10 M 0.5 6.5 A 3 6.5 2.5 2.5 180 300"),
+    ('s', 6.5, "A 3 5.5 2.3 1.5 -20 -270 A 3 8.5 2.3 1.5 -90 160"),
+    ('t', 5.0, "M 2 1 L 2 9 A 3.5 9 1.5 1 180 60 M 0.5 4 L 4 4"),
+    ('u', 7.0, "M 0.5 4 L 0.5 7.5 A 3 7.5 2.5 2.5 180 0 M 5.5 4 L 5.5 10"),
+    ('v', 6.5, "M 0.5 4 L 3 10 L 5.5 4"),
+    ('w', 8.5, "M 0.5 4 L 2.25 10 L 4 5 L 5.75 10 L 7.5 4"),
+    ('x', 6.5, "M 0.5 4 L 5.5 10 M 5.5 4 L 0.5 10"),
+    ('y', 6.5, "M 0.5 4 L 3 10 M 5.5 4 L 2.5 11.5 L 1.8 12.8 L 0.8 13"),
+    ('z', 6.5, "M 0.5 4 L 5.5 4 L 0.5 10 L 5.5 10"),
+    ('{', 4.5, "M 3.5 -0.5 L 2.5 -0.5 L 2 0.5 L 2 4 L 0.5 5.25 L 2 6.5 L 2 10 L 2.5 11 L 3.5 11"),
+    ('|', 2.5, "M 1 -0.5 L 1 11"),
+    ('}', 4.5, "M 0.5 -0.5 L 1.5 -0.5 L 2 0.5 L 2 4 L 3.5 5.25 L 2 6.5 L 2 10 L 1.5 11 L 0.5 11"),
+    ('~', 6.5, "A 1.75 6 1.25 1 180 360 A 4.25 6 1.25 1 180 0"),
+];
+
+// Characters without a glyph are written as this
+const MISSING_GLYPH: char = '?';
+
+struct Glyph {
+    advance: f32,
+    strokes: Vec<Polyline>,
+}
+
+fn glyphs() -> &'static HashMap<char, Glyph> {
+    static GLYPH_MAP: OnceLock<HashMap<char, Glyph>> = OnceLock::new();
+    GLYPH_MAP.get_or_init(|| {
+        GLYPHS
+            .iter()
+            .map(|&(c, advance, path)| {
+                (
+                    c,
+                    Glyph {
+                        advance,
+                        strokes: parse_glyph_path(path),
+                    },
+                )
+            })
+            .collect()
+    })
+}
+
+fn parse_glyph_path(path: &str) -> Vec<Polyline> {
+    let mut strokes = Vec::new();
+    let mut current: Polyline = Vec::new();
+    let mut tokens = path.split_whitespace();
+    let number = |tokens: &mut std::str::SplitWhitespace| tokens.next().and_then(|t| t.parse::<f32>().ok()).unwrap_or(0.0);
+
+    while let Some(command) = tokens.next() {
+        match command {
+            "M" => {
+                if current.len() > 1 {
+                    strokes.push(std::mem::take(&mut current));
+                }
+                current = vec![(number(&mut tokens), number(&mut tokens))];
+            }
+            "L" => current.push((number(&mut tokens), number(&mut tokens))),
+            "A" => {
+                let (cx, cy, rx, ry) = (number(&mut tokens), number(&mut tokens), number(&mut tokens), number(&mut tokens));
+                let (from, to) = (number(&mut tokens).to_radians(), number(&mut tokens).to_radians());
+                let steps = ((to - from).abs() / std::f32::consts::TAU * ARC_STEPS).ceil().max(1.0) as usize;
+                for step in 0..=steps {
+                    let angle = from + (to - from) * step as f32 / steps as f32;
+                    let point = (cx + rx * angle.cos(), cy + ry * angle.sin());
+                    // The arc carries on from where the stroke is, if it isn't already there
+                    let joined = current
+                        .last()
+                        .is_some_and(|last| (last.0 - point.0).abs() < 0.01 && (last.1 - point.1).abs() < 0.01);
+                    if !joined {
+                        current.push(point);
+                    }
+                }
+            }
+            _ => debug!("Unknown glyph path command {}", command),
+        }
+    }
+    if current.len() > 1 {
+        strokes.push(current);
+    }
+    strokes
+}
+
+fn glyph_for(c: char) -> &'static Glyph {
+    let glyphs = glyphs();
+    glyphs.get(&c).unwrap_or_else(|| &glyphs[&MISSING_GLYPH])
+}
+
+/// Where and how to write, in virtual pixels
+#[derive(Debug, Clone, Copy)]
+pub struct Handwriting {
+    /// Left edge of the text
+    pub x: f32,
+    /// Top of the first line's capitals
+    pub y: f32,
+    /// Height of capital letters
+    pub size: f32,
+    /// Wrap lines to this width
+    pub max_width: Option<f32>,
+    /// Lean to the right, in degrees
+    pub slant: f32,
+}
+
+impl Handwriting {
+    /// Check the model asked for writing that fits on the page and can be read
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..VIRTUAL_WIDTH).contains(&self.x) || !(0.0..VIRTUAL_HEIGHT).contains(&self.y) {
+            return Err(anyhow::anyhow!(
+                "x and y must be on the {}x{} screen, got ({}, {})",
+                VIRTUAL_WIDTH,
+                VIRTUAL_HEIGHT,
+                self.x,
+                self.y
+            ));
+        }
+        if !(MIN_SIZE..=MAX_SIZE).contains(&self.size) {
+            return Err(anyhow::anyhow!("size must be between {} and {} px, got {}", MIN_SIZE, MAX_SIZE, self.size));
+        }
+        if self.max_width.is_some_and(|width| !(self.size..=VIRTUAL_WIDTH).contains(&width)) {
+            return Err(anyhow::anyhow!(
+                "max_width must be between the size and the screen width, got {:?}",
+                self.max_width
+            ));
+        }
+        if !(-MAX_SLANT..=MAX_SLANT).contains(&self.slant) {
+            return Err(anyhow::anyhow!(
+                "slant must be between -{} and {} degrees, got {}",
+                MAX_SLANT,
+                MAX_SLANT,
+                self.slant
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The pen strokes that write `text`, one per glyph stroke, wrapping at spaces where a line
+/// would get wider than `max_width` (or anywhere, for a word that is wider on its own).
+/// Fails if the writing would run off the screen.
+pub fn handwriting_strokes(text: &str, options: &Handwriting) -> Result<Vec<Polyline>> {
+    let scale = options.size / CAP_HEIGHT;
+    let max_width = options.max_width.map(|width| width / scale);
+    let shear = options.slant.to_radians().tan();
+
+    let mut strokes = Vec::new();
+    for (line_number, line) in wrap_lines(text, max_width).iter().enumerate() {
+        let top = options.y + line_number as f32 * LINE_HEIGHT * scale;
+        let mut pen_x = 0.0;
+        for c in line.chars() {
+            let glyph = glyph_for(c);
+            for stroke in &glyph.strokes {
+                strokes.push(
+                    stroke
+                        .iter()
+                        .map(|&(x, y)| (options.x + (pen_x + x + (BASELINE - y) * shear) * scale, top + y * scale))
+                        .collect(),
+                );
+            }
+            pen_x += glyph.advance + LETTER_SPACING;
+        }
+    }
+    check_on_screen(&strokes)?;
+    Ok(strokes)
+}
+
+/// Refuse writing that spills past the edge of the screen rather than cutting letters off
+fn check_on_screen(strokes: &[Polyline]) -> Result<()> {
+    let (min_x, min_y, max_x, max_y) = strokes.iter().flatten().fold(
+        (f32::INFINITY, f32::INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        |(min_x, min_y, max_x, max_y), &(x, y)| (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+    );
+    if min_x < 0.0 || min_y < 0.0 || max_x > VIRTUAL_WIDTH - 1.0 || max_y > VIRTUAL_HEIGHT - 1.0 {
+        return Err(anyhow::anyhow!(
+            "the text would cover ({:.0}, {:.0}) to ({:.0}, {:.0}), past the edge of the {}x{} screen; \
+             use a smaller size, set max_width, or move it",
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            VIRTUAL_WIDTH,
+            VIRTUAL_HEIGHT
+        ));
+    }
+    Ok(())
+}
+
+fn text_width(text: &str) -> f32 {
+    text.chars().map(|c| glyph_for(c).advance + LETTER_SPACING).sum()
+}
+
+/// Split text into lines no wider than `max_width` glyph units
+fn wrap_lines(text: &str, max_width: Option<f32>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.replace('\t', "    ").lines() {
+        let Some(max_width) = max_width else {
+            lines.push(paragraph.to_string());
+            continue;
+        };
+        let mut line = String::new();
+        for word in paragraph.split(' ') {
+            let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+            if text_width(&candidate) <= max_width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            if text_width(word) <= max_width {
+                line = word.to_string();
+                continue;
+            }
+            // Break words that don't fit on a line of their own
+            for c in word.chars() {
+                if !line.is_empty() && text_width(&line) + glyph_for(c).advance + LETTER_SPACING > max_width {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line.push(c);
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32, y: f32, size: f32) -> Handwriting {
+        Handwriting {
+            x,
+            y,
+            size,
+            max_width: None,
+            slant: 0.0,
+        }
+    }
+
+    #[test]
+    fn every_glyph_stays_on_its_grid() {
+        for &(c, advance, _) in GLYPHS {
+            let glyph = &glyphs()[&c];
+            assert_eq!(glyph.strokes.is_empty(), c == ' ', "glyph {:?}", c);
+            // Descenders like j's hook may tuck under their neighbour a little
+            for &(x, y) in glyph.strokes.iter().flatten() {
+                assert!(
+                    (-2.0..=advance + 1.0).contains(&x) && (-0.5..=13.5).contains(&y),
+                    "glyph {:?} has ({}, {})",
+                    c,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lines_wrap_at_spaces_to_fit_max_width() {
+        // 'l' is 3 wide and a space 4, each plus 1 of letter spacing
+        assert_eq!(text_width("ll ll"), 21.0);
+        assert_eq!(wrap_lines("ll ll ll", Some(21.0)), ["ll ll", "ll"]);
+        assert_eq!(wrap_lines("ll ll ll", Some(20.0)), ["ll", "ll", "ll"]);
+        assert_eq!(wrap_lines("ll ll ll", None), ["ll ll ll"]);
+        assert_eq!(wrap_lines("ll\nll ll", Some(21.0)), ["ll", "ll ll"]);
+    }
+
+    #[test]
+    fn words_wider_than_a_line_are_broken() {
+        assert_eq!(wrap_lines("llllll", Some(10.0)), ["ll", "ll", "ll"]);
+        assert_eq!(wrap_lines("l llllll", Some(10.0)), ["l", "ll", "ll", "ll"]);
+    }
+
+    #[test]
+    fn strokes_are_placed_scaled_and_slanted() {
+        // 'l' is a single line from the top of the capitals to the baseline, one unit in
+        assert_eq!(
+            handwriting_strokes("l", &at(100.0, 200.0, 20.0)).unwrap(),
+            [vec![(102.0, 200.0), (102.0, 220.0)]]
+        );
+
+        let slanted = Handwriting {
+            slant: 45.0,
+            ..at(100.0, 200.0, 20.0)
+        };
+        let stroke = &handwriting_strokes("l", &slanted).unwrap()[0];
+        assert!((stroke[0].0 - 122.0).abs() < 1e-3 && (stroke[1].0 - 102.0).abs() < 1e-3, "{:?}", stroke);
+
+        // The second line starts back at x, one line height further down
+        let wrapped = Handwriting {
+            max_width: Some(40.0),
+            ..at(100.0, 200.0, 20.0)
+        };
+        let strokes = handwriting_strokes("ll ll", &wrapped).unwrap();
+        assert_eq!(strokes.len(), 4);
+        assert_eq!(strokes[2], vec![(102.0, 200.0 + LINE_HEIGHT * 2.0), (102.0, 220.0 + LINE_HEIGHT * 2.0)]);
+    }
+
+    #[test]
+    fn unknown_characters_are_written_as_question_marks() {
+        let options = at(100.0, 100.0, 20.0);
+        assert_eq!(handwriting_strokes("\u{263A}", &options).unwrap(), handwriting_strokes("?", &options).unwrap());
+    }
+
+    #[test]
+    fn writing_off_the_screen_is_refused() {
+        assert!(handwriting_strokes("hello world", &at(600.0, 100.0, 40.0)).is_err());
+        assert!(handwriting_strokes("hello", &at(100.0, 1000.0, 40.0)).is_err());
+        let wrapped = Handwriting {
+            max_width: Some(160.0),
+            ..at(600.0, 100.0, 20.0)
+        };
+        assert!(handwriting_strokes("hello world", &wrapped).is_ok());
+    }
+
+    #[test]
+    fn bad_requests_fail_validation() {
+        assert!(at(100.0, 100.0, 24.0).validate().is_ok());
+        assert!(at(-1.0, 100.0, 24.0).validate().is_err());
+        assert!(at(100.0, 1024.0, 24.0).validate().is_err());
+        assert!(at(100.0, 100.0, 3.0).validate().is_err());
+        assert!(at(100.0, 100.0, 500.0).validate().is_err());
+        assert!(Handwriting {
+            slant: 60.0,
+            ..at(100.0, 100.0, 24.0)
+        }
+        .validate()
+        .is_err());
+        assert!(Handwriting {
+            max_width: Some(10.0),
+            ..at(100.0, 100.0, 24.0)
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use chrono::{DateTime, Local};
+use serde_json::Value as json;
+use std::collections::VecDeque;
+
+const MAX_SUMMARY_LENGTH: usize = 200;
+
+pub struct Interaction {
+    pub time: DateTime<Local>,
+    pub tool: String,
+    pub summary: String,
+}
+
+/// The last few things the assistant did, so a prompt can refer back to them
+pub struct InteractionHistory {
+    entries: VecDeque<Interaction>,
+    max_entries: usize,
+}
+
+impl InteractionHistory {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            max_entries,
+        }
+    }
+
+    pub fn record(&mut self, tool: &str, arguments: &json) {
+        self.entries.push_back(Interaction {
+            time: Local::now(),
+            tool: tool.to_string(),
+            summary: Self::summarize(arguments),
+        });
+        while self.entries.len() > self.max_entries {
+            self.entries.pop_front();
+        }
+    }
+
+    /// One line per interaction, oldest first
+    pub fn describe(&self) -> String {
+        if self.entries.is_empty() {
+            return "No previous interactions.".to_string();
+        }
+        self.entries
+            .iter()
+            .map(|entry| format!("{} {}: {}", entry.time.format("%H:%M"), entry.tool, entry.summary))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Prefer the model's own description of its output, then the text it wrote, then the raw arguments
+    fn summarize(arguments: &json) -> String {
+        let summary = ["output_description", "text"]
+            .iter()
+            .find_map(|key| arguments[key].as_str().map(String::from))
+            .unwrap_or_else(|| arguments.to_string());
+        let summary = summary.replace('\n', " ");
+        if summary.chars().count() > MAX_SUMMARY_LENGTH {
+            format!("{}...", summary.chars().take(MAX_SUMMARY_LENGTH).collect::<String>())
+        } else {
+            summary
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::Result;
+use serde_json::json;
+
+use crate::pen_input::{bounds_of, PenPoint, PenStroke};
+
+// Output dimensions remain the same for both devices
+const VIRTUAL_WIDTH: u32 = 768;
+const VIRTUAL_HEIGHT: u32 = 1024;
+
+// Points closer than this to the previous kept point add nothing but tokens
+const MIN_POINT_SPACING: i32 = 2;
+
+/// How the user's strokes are described to the model, from `--ink-context`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InkFormat {
+    Svg,
+    Json,
+}
+
+impl InkFormat {
+    pub fn from_string(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "svg" => Ok(InkFormat::Svg),
+            "json" => Ok(InkFormat::Json),
+            _ => Err(anyhow::anyhow!("Invalid ink context format: {}. Use svg or json", s)),
+        }
+    }
+}
+
+/// The strokes the user has drawn since the last interaction, oldest first
+#[derive(Default)]
+pub struct InkLog {
+    strokes: Vec<PenStroke>,
+}
+
+impl InkLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, stroke: PenStroke) {
+        self.strokes.push(stroke);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strokes.is_empty()
+    }
+
+    pub fn pop(&mut self) -> Option<PenStroke> {
+        self.strokes.pop()
+    }
+
+    pub fn extend(&mut self, strokes: Vec<PenStroke>) {
+        self.strokes.extend(strokes);
+    }
+
+    /// Hand over everything collected so far and start afresh
+    pub fn take(&mut self) -> Vec<PenStroke> {
+        std::mem::take(&mut self.strokes)
+    }
+}
+
+/// The (x, y, width, height) box around all the strokes, if there are any
+pub fn ink_bounds(strokes: &[PenStroke]) -> Option<(i32, i32, i32, i32)> {
+    if strokes.iter().all(|stroke| stroke.points.is_empty()) {
+        return None;
+    }
+    Some(bounds_of(strokes.iter().flat_map(|stroke| stroke.points.iter())))
+}
+
+pub fn describe_ink_bounds(strokes: &[PenStroke]) -> String {
+    match ink_bounds(strokes) {
+        Some((x, y, width, height)) => format!("x={} y={} width={} height={}", x, y, width, height),
+        None => "none".to_string(),
+    }
+}
+
+/// Describe the strokes in the given format, in drawing order with times relative to the first stroke
+pub fn describe_ink(strokes: &[PenStroke], format: InkFormat) -> String {
+    let Some(first_time) = strokes.first().map(|stroke| stroke.start_time()) else {
+        return "none".to_string();
+    };
+    match format {
+        InkFormat::Svg => ink_to_svg(strokes, first_time),
+        InkFormat::Json => ink_to_json(strokes, first_time),
+    }
+}
+
+fn ink_to_svg(strokes: &[PenStroke], first_time: f64) -> String {
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+        VIRTUAL_WIDTH, VIRTUAL_HEIGHT
+    );
+    for (i, stroke) in strokes.iter().enumerate() {
+        let points = simplify(&stroke.points);
+        let path = points
+            .iter()
+            .enumerate()
+            .map(|(j, point)| format!("{}{} {}", if j == 0 { "M" } else { "L" }, point.x, point.y))
+            .collect::<Vec<_>>()
+            .join(" ");
+        svg.push_str(&format!(
+            "  <path id=\"stroke-{}\" data-start=\"{:.2}\" data-duration=\"{:.2}\" d=\"{}\" fill=\"none\" stroke=\"black\"/>\n",
+            i + 1,
+            stroke.start_time() - first_time,
+            stroke.end_time() - stroke.start_time(),
+            path
+        ));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn ink_to_json(strokes: &[PenStroke], first_time: f64) -> String {
+    let strokes = strokes
+        .iter()
+        .map(|stroke| {
+            json!({
+                "start": round2(stroke.start_time() - first_time),
+                "duration": round2(stroke.end_time() - stroke.start_time()),
+                // [x, y, pressure]
+                "points": simplify(&stroke.points).iter().map(|point| json!([point.x, point.y, point.pressure])).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "strokes": strokes }).to_string()
+}
+
+fn round2(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Drop points that barely moved from the last one kept, always keeping the ends
+fn simplify(points: &[PenPoint]) -> Vec<PenPoint> {
+    let mut kept: Vec<PenPoint> = Vec::new();
+    for (i, point) in points.iter().enumerate() {
+        let is_last = i == points.len() - 1;
+        match kept.last() {
+            Some(last) if !is_last && (point.x - last.x).abs() < MIN_POINT_SPACING && (point.y - last.y).abs() < MIN_POINT_SPACING => {}
+            _ => kept.push(*point),
+        }
+    }
+    kept
+}
@@ -0,0 +1,278 @@
+use anyhow::Result;
+use log::warn;
+
+use crate::pen::{Stroke, MOVE_EVENTS, STROKE_EVENTS};
+use crate::stroke_planner::bitmap_runs;
+
+// Input events Pen writes for a stroke, and for each move while the pen is down
+const EVENTS_PER_STROKE: u64 = STROKE_EVENTS as u64;
+const EVENTS_PER_MOVE: u64 = MOVE_EVENTS as u64;
+// Scaling down shrinks by this much each time the drawing still doesn't fit, down to MIN_SCALE
+const SCALE_STEP: f32 = 0.9;
+const MIN_SCALE: f32 = 0.1;
+
+/// What to do with a drawing that needs more ink or pen events than allowed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InkLimitAction {
+    /// Don't draw it at all
+    Refuse,
+    /// Draw only the outlines of its shapes
+    Outline,
+    /// Shrink it, towards its top left corner, until it fits
+    Scale,
+}
+
+impl InkLimitAction {
+    pub fn from_string(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "refuse" => Ok(InkLimitAction::Refuse),
+            "outline" => Ok(InkLimitAction::Outline),
+            "scale" => Ok(InkLimitAction::Scale),
+            _ => Err(anyhow::anyhow!("Invalid ink limit action: {}. Use refuse, outline or scale", s)),
+        }
+    }
+}
+
+/// How much a drawing costs: virtual pixels of pen travel with the pen down, and input events
+#[derive(Debug, Clone, Copy)]
+pub struct InkCost {
+    pub ink_pixels: u64,
+    pub pen_events: u64,
+}
+
+impl InkCost {
+    /// The cost of drawing strokes, either one move per pixel (`interpolated`) or one per point
+    pub fn of_strokes(strokes: &[Stroke], interpolated: bool) -> Self {
+        let mut cost = InkCost { ink_pixels: 0, pen_events: 0 };
+        for stroke in strokes {
+            cost.pen_events += EVENTS_PER_STROKE;
+            for pair in stroke.points.windows(2) {
+                let (dx, dy) = ((pair[1].0 - pair[0].0).abs(), (pair[1].1 - pair[0].1).abs());
+                cost.ink_pixels += (dx * dx + dy * dy).sqrt().round() as u64;
+                let moves = if interpolated { dx.max(dy).ceil().max(1.0) as u64 } else { 1 };
+                cost.pen_events += EVENTS_PER_MOVE * moves;
+            }
+        }
+        cost
+    }
+
+    /// The cost of drawing a bitmap as runs, see `Pen::draw_bitmap`
+    pub fn of_bitmap(bitmap: &[Vec<bool>], skip_scanlines: bool, scale: u32) -> Self {
+        let runs: Vec<Stroke> = bitmap_runs(bitmap, skip_scanlines, scale).into_iter().map(Stroke::from).collect();
+        Self::of_strokes(&runs, false)
+    }
+}
+
+impl std::fmt::Display for InkCost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ink pixels and {} pen events", self.ink_pixels, self.pen_events)
+    }
+}
+
+/// Hard limits on how much the pen may draw for one tool call; 0 means no limit
+#[derive(Debug, Clone, Copy)]
+pub struct InkLimits {
+    pub max_ink_pixels: u64,
+    pub max_pen_events: u64,
+    pub action: InkLimitAction,
+}
+
+impl InkLimits {
+    fn allows(&self, cost: InkCost) -> bool {
+        (self.max_ink_pixels == 0 || cost.ink_pixels <= self.max_ink_pixels) && (self.max_pen_events == 0 || cost.pen_events <= self.max_pen_events)
+    }
+
+    /// How far over the limits a cost is, as the largest ratio of cost to limit
+    fn overshoot(&self, cost: InkCost) -> f32 {
+        let ratio = |used: u64, max: u64| if max == 0 { 0.0 } else { used as f32 / max as f32 };
+        ratio(cost.ink_pixels, self.max_ink_pixels).max(ratio(cost.pen_events, self.max_pen_events))
+    }
+
+    fn refuse(&self, cost: InkCost) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Drawing needs {}, over the limit of {} ink pixels and {} pen events",
+            cost,
+            self.max_ink_pixels,
+            self.max_pen_events
+        )
+    }
+
+    /// Shrink with `scale` until the result fits, starting from `factor`
+    fn scale_to_fit<T>(&self, mut factor: f32, scale: impl Fn(f32) -> (T, InkCost)) -> Result<T> {
+        loop {
+            let factor_used = factor.min(1.0);
+            let (scaled, cost) = scale(factor_used);
+            if self.allows(cost) {
+                warn!("Scaled the drawing down to {:.0}% to stay within the ink limits", factor_used * 100.0);
+                return Ok(scaled);
+            }
+            factor = factor_used * SCALE_STEP;
+            if factor < MIN_SCALE {
+                return Err(self.refuse(cost));
+            }
+        }
+    }
+
+    /// Strokes drawn one move per pixel, made to fit the limits; `outline` gives the outline-only
+    /// version of the drawing
+    pub fn fit_strokes(&self, strokes: Vec<Stroke>, outline: impl FnOnce() -> Result<Vec<Stroke>>) -> Result<Vec<Stroke>> {
+        let cost = InkCost::of_strokes(&strokes, true);
+        if self.allows(cost) {
+            return Ok(strokes);
+        }
+        warn!("Drawing needs {}, more than allowed", cost);
+        match self.action {
+            InkLimitAction::Refuse => Err(self.refuse(cost)),
+            InkLimitAction::Outline => {
+                let outline = outline()?;
+                let outline_cost = InkCost::of_strokes(&outline, true);
+                if !self.allows(outline_cost) {
+                    return Err(self.refuse(outline_cost));
+                }
+                warn!("Drawing only the outlines to stay within the ink limits");
+                Ok(outline)
+            }
+            // Ink and events both grow about linearly with the size of stroke drawings
+            InkLimitAction::Scale => self.scale_to_fit(1.0 / self.overshoot(cost), |factor| {
+                let scaled = scale_strokes(&strokes, factor);
+                let cost = InkCost::of_strokes(&scaled, true);
+                (scaled, cost)
+            }),
+        }
+    }
+
+    /// A bitmap made to fit the limits, for `Pen::draw_bitmap` with the same `skip_scanlines`
+    /// and `scale`
+    pub fn fit_bitmap(&self, bitmap: Vec<Vec<bool>>, skip_scanlines: bool, scale: u32) -> Result<Vec<Vec<bool>>> {
+        let cost = InkCost::of_bitmap(&bitmap, skip_scanlines, scale);
+        if self.allows(cost) {
+            return Ok(bitmap);
+        }
+        warn!("Drawing needs {}, more than allowed", cost);
+        match self.action {
+            InkLimitAction::Refuse => Err(self.refuse(cost)),
+            InkLimitAction::Outline => {
+                let outline = outline_bitmap(&bitmap);
+                let outline_cost = InkCost::of_bitmap(&outline, skip_scanlines, scale);
+                if !self.allows(outline_cost) {
+                    return Err(self.refuse(outline_cost));
+                }
+                warn!("Drawing only the outlines to stay within the ink limits");
+                Ok(outline)
+            }
+            // Ink grows with the area of a bitmap, so start from the square root
+            InkLimitAction::Scale => self.scale_to_fit((1.0 / self.overshoot(cost)).sqrt(), |factor| {
+                let scaled = scale_bitmap(&bitmap, factor);
+                let cost = InkCost::of_bitmap(&scaled, skip_scanlines, scale);
+                (scaled, cost)
+            }),
+        }
+    }
+}
+
+/// Shrink strokes by `factor` towards the top left corner of their bounding box
+fn scale_strokes(strokes: &[Stroke], factor: f32) -> Vec<Stroke> {
+    let origin = strokes
+        .iter()
+        .flat_map(|stroke| &stroke.points)
+        .fold((f32::MAX, f32::MAX), |(min_x, min_y), &(x, y)| (min_x.min(x), min_y.min(y)));
+    strokes
+        .iter()
+        .map(|stroke| Stroke {
+            points: stroke
+                .points
+                .iter()
+                .map(|&(x, y)| (origin.0 + (x - origin.0) * factor, origin.1 + (y - origin.1) * factor))
+                .collect(),
+            style: stroke.style,
+        })
+        .collect()
+}
+
+/// Shrink a bitmap by `factor` towards the top left corner of its inked pixels, keeping its size
+fn scale_bitmap(bitmap: &[Vec<bool>], factor: f32) -> Vec<Vec<bool>> {
+    let inked = || {
+        bitmap
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().filter(|(_, &pixel)| pixel).map(move |(x, _)| (x, y)))
+    };
+    let (Some(left), Some(top)) = (inked().map(|(x, _)| x).min(), inked().map(|(_, y)| y).min()) else {
+        return bitmap.to_vec();
+    };
+
+    let mut scaled: Vec<Vec<bool>> = bitmap.iter().map(|row| vec![false; row.len()]).collect();
+    for (y, row) in scaled.iter_mut().enumerate().skip(top) {
+        let source_y = top + ((y - top) as f32 / factor) as usize;
+        let Some(source_row) = bitmap.get(source_y) else {
+            break;
+        };
+        for (x, pixel) in row.iter_mut().enumerate().skip(left) {
+            *pixel = source_row.get(left + ((x - left) as f32 / factor) as usize).copied().unwrap_or(false);
+        }
+    }
+    scaled
+}
+
+/// Just the inked pixels that have an empty pixel (or the edge) beside them
+fn outline_bitmap(bitmap: &[Vec<bool>]) -> Vec<Vec<bool>> {
+    let inked = |x: isize, y: isize| x >= 0 && y >= 0 && bitmap.get(y as usize).and_then(|row| row.get(x as usize)).copied().unwrap_or(false);
+    bitmap
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(x, &pixel)| {
+                    let (x, y) = (x as isize, y as isize);
+                    pixel && !(inked(x - 1, y) && inked(x + 1, y) && inked(x, y - 1) && inked(x, y + 1))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pen::Pen;
+    use crate::trace::{read_trace, TraceRecorder};
+
+    /// The events Pen actually sends for a drawing, from a trace of it
+    fn recorded_events(name: &str, draw: impl FnOnce(&mut Pen) -> Result<()>) -> u64 {
+        let path = std::env::temp_dir().join(format!("ghostwriter-ink-limit-{}-{}.trace", name, std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut pen = Pen::new(true, None).unwrap();
+        pen.set_recorder(TraceRecorder::create(path).unwrap());
+        draw(&mut pen).unwrap();
+        let events = read_trace(path).unwrap().len() as u64;
+        std::fs::remove_file(path).unwrap();
+        events
+    }
+
+    #[test]
+    fn stroke_cost_matches_recorded_events() {
+        let strokes: Vec<Stroke> = vec![
+            vec![(10.0, 10.0), (50.0, 10.0), (50.0, 40.0)].into(),
+            vec![(200.0, 300.0), (230.5, 340.2)].into(),
+            vec![(400.0, 100.0), (400.0, 100.6)].into(),
+        ];
+        let cost = InkCost::of_strokes(&strokes, true);
+        assert_eq!(recorded_events("strokes", |pen| pen.draw_strokes(strokes)), cost.pen_events);
+    }
+
+    // Planning joins runs whose ends touch, so a bitmap can take fewer events than estimated,
+    // never more
+    #[test]
+    fn bitmap_cost_covers_recorded_events() {
+        let mut bitmap = vec![vec![false; 64]; 48];
+        for (y, row) in bitmap.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = (8..40).contains(&x) && (4..30).contains(&y) || x == 50;
+            }
+        }
+        let cost = InkCost::of_bitmap(&bitmap, true, 2);
+        let recorded = recorded_events("bitmap", |pen| pen.draw_bitmap(&bitmap, true, 2));
+        assert!(recorded <= cost.pen_events, "{} events recorded, {} estimated", recorded, cost.pen_events);
+    }
+}
@@ -4,6 +4,8 @@ use log::debug;
 use std::collections::HashMap;
 use std::{thread, time};
 
+use crate::cancel::CancelToken;
+
 use evdev::{uinput::VirtualDevice, AttributeSet, EventType as EvdevEventType, InputEvent, KeyCode as EvdevKey};
 
 pub struct Keyboard {
@@ -11,6 +13,7 @@ pub struct Keyboard {
     key_map: HashMap<char, (EvdevKey, bool)>,
     progress_count: u32,
     no_draw_progress: bool,
+    cancel: CancelToken,
 }
 
 impl Keyboard {
@@ -22,9 +25,15 @@ impl Keyboard {
             key_map: Self::create_key_map(),
             progress_count: 0,
             no_draw_progress,
+            cancel: CancelToken::new(),
         }
     }
 
+    /// `type_text` stops early once this token is cancelled
+    pub fn set_cancel_token(&mut self, cancel: CancelToken) {
+        self.cancel = cancel;
+    }
+
     fn create_virtual_device() -> VirtualDevice {
         debug!("Creating virtual keyboard");
         let mut keys = AttributeSet::new();
@@ -237,12 +246,25 @@ impl Keyboard {
     }
 
     pub fn string_to_keypresses(&mut self, input: &str) -> Result<()> {
+        self.emit_keypresses(input, None)
+    }
+
+    /// Type out a response; unlike `string_to_keypresses` this can be cancelled part way through
+    pub fn type_text(&mut self, text: &str) -> Result<()> {
+        let cancel = self.cancel.clone();
+        self.emit_keypresses(text, Some(&cancel))
+    }
+
+    fn emit_keypresses(&mut self, input: &str, cancel: Option<&CancelToken>) -> Result<()> {
         if let Some(device) = &mut self.device {
             // make sure we are synced before we start; this might be paranoia
             device.emit(&[InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0)])?;
             thread::sleep(time::Duration::from_millis(10));
 
             for c in input.chars() {
+                if let Some(cancel) = cancel {
+                    cancel.check()?;
+                }
                 if let Some(&(key, shift)) = self.key_map.get(&c) {
                     if shift {
                         // Press Shift
@@ -302,6 +324,11 @@ impl Keyboard {
         Ok(())
     }
 
+    pub fn key_cmd_undo(&mut self) -> Result<()> {
+        self.key_cmd("z", false)?;
+        Ok(())
+    }
+
     pub fn progress(&mut self, note: &str) -> Result<()> {
         if self.no_draw_progress {
             return Ok(());
@@ -1,10 +1,26 @@
+pub mod cancel;
 pub mod config;
+pub mod confirm;
 pub mod device;
 pub mod embedded_assets;
+pub mod external_command;
+pub mod gesture;
+pub mod handwriting;
+pub mod history;
+pub mod ink;
+pub mod ink_limit;
 pub mod keyboard;
 pub mod llm_engine;
 pub mod pen;
+pub mod pen_input;
+pub mod pen_symbol;
+pub mod prompt;
+pub mod router;
 pub mod screenshot;
 pub mod segmenter;
+pub mod stroke_planner;
+pub mod svg_paths;
 pub mod touch;
+pub mod trace;
 pub mod util;
+pub mod xochitl;
@@ -1,4 +1,5 @@
-use super::LLMEngine;
+use super::{post_json, tool_parameters, Example, LLMEngine, ToolCallback, ToolChoice, EXAMPLE_TOOL_RESULT, MAX_TOOL_ROUNDS};
+use crate::cancel::CancelToken;
 use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
 use anyhow::Result;
 use log::debug;
@@ -8,7 +9,7 @@ use serde_json::Value as json;
 pub struct Tool {
     name: String,
     definition: json,
-    callback: Option<Box<dyn FnMut(json)>>,
+    callback: Option<ToolCallback>,
 }
 
 pub struct Anthropic {
@@ -17,9 +18,14 @@ pub struct Anthropic {
     base_url: String,
     tools: Vec<Tool>,
     content: Vec<json>,
+    examples: Vec<Example>,
     web_search: bool,
     thinking: bool,
     thinking_tokens: u32,
+    max_tokens: u32,
+    temperature: Option<f32>,
+    tool_choice: ToolChoice,
+    cancel: CancelToken,
 }
 
 impl Anthropic {
@@ -27,11 +33,64 @@ impl Anthropic {
         self.content.push(content);
     }
 
+    fn image_content(base64_image: &str) -> json {
+        json!({
+            "type": "image",
+            "source": {
+                "type": "base64",
+                "media_type": "image/png",
+                "data": base64_image
+            }
+        })
+    }
+
+    /// Examples become user/assistant turns ahead of the real content; each example's
+    /// tool result has to lead the following user turn
+    fn initial_messages(&self) -> Vec<json> {
+        let mut messages = Vec::new();
+        let mut pending_result: Option<json> = None;
+
+        for (i, example) in self.examples.iter().enumerate() {
+            let tool_use_id = format!("example_{}", i);
+            let mut content = pending_result.take().into_iter().collect::<Vec<_>>();
+            content.push(Self::image_content(&example.base64_image));
+            messages.push(json!({ "role": "user", "content": content }));
+            messages.push(json!({
+                "role": "assistant",
+                "content": [{
+                    "type": "tool_use",
+                    "id": tool_use_id,
+                    "name": example.tool_name,
+                    "input": example.arguments
+                }]
+            }));
+            pending_result = Some(json!({
+                "type": "tool_result",
+                "tool_use_id": tool_use_id,
+                "content": EXAMPLE_TOOL_RESULT
+            }));
+        }
+
+        let mut content = pending_result.into_iter().collect::<Vec<_>>();
+        content.extend(self.content.iter().cloned());
+        messages.push(json!({ "role": "user", "content": content }));
+        messages
+    }
+
+    fn anthropic_tool_choice(&self) -> json {
+        match &self.tool_choice {
+            ToolChoice::Auto => json!({ "type": "auto" }),
+            ToolChoice::Any => json!({ "type": "any" }),
+            ToolChoice::None => json!({ "type": "none" }),
+            ToolChoice::Tool(name) => json!({ "type": "tool", "name": name }),
+        }
+    }
+
     fn anthropic_tool_definition(tool: &Tool) -> json {
         json!({
             "name": tool.definition["name"],
             "description": tool.definition["description"],
-            "input_schema": tool.definition["parameters"],
+            "input_schema": tool_parameters(&tool.definition),
         })
     }
 }
@@ -44,6 +103,9 @@ impl LLMEngine for Anthropic {
         let web_search = options.get("web_search").is_some_and(|v| v == "true");
         let thinking = options.get("thinking").is_some_and(|v| v == "true");
         let thinking_tokens = options.get("thinking_tokens").and_then(|v| v.parse::<u32>().ok()).unwrap_or(5000);
+        let max_tokens = options.get("max_tokens").and_then(|v| v.parse::<u32>().ok()).unwrap_or(10000);
+        let temperature = options.get("temperature").and_then(|v| v.parse::<f32>().ok());
+        let tool_choice = ToolChoice::from_options(options, ToolChoice::Auto);
 
         Self {
             model,
@@ -51,13 +113,18 @@ impl LLMEngine for Anthropic {
             api_key,
             tools: Vec::new(),
             content: Vec::new(),
+            examples: Vec::new(),
             web_search,
             thinking,
             thinking_tokens,
+            max_tokens,
+            temperature,
+            tool_choice,
+            cancel: CancelToken::new(),
         }
     }
 
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>) {
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback) {
         self.tools.push(Tool {
             name: name.to_string(),
             definition,
@@ -73,20 +140,25 @@ impl LLMEngine for Anthropic {
     }
 
     fn add_image_content(&mut self, base64_image: &str) {
-        self.add_content(json!({
-            "type": "image",
-            "source": {
-                "type": "base64",
-                "media_type": "image/png",
-                "data": base64_image
-            }
-        }));
+        self.add_content(Self::image_content(base64_image));
+    }
+
+    fn add_example(&mut self, example: Example) {
+        if self.tools.iter().any(|tool| tool.name == example.tool_name) {
+            self.examples.push(example);
+        } else {
+            debug!("Skipping example for unregistered tool {}", example.tool_name);
+        }
     }
 
     fn clear_content(&mut self) {
         self.content.clear();
     }
 
+    fn set_cancel_token(&mut self, cancel: CancelToken) {
+        self.cancel = cancel;
+    }
+
     fn execute(&mut self) -> Result<()> {
         let mut tool_definitions = self.tools.iter().map(Self::anthropic_tool_definition).collect::<Vec<_>>();
 
@@ -99,87 +171,118 @@ impl LLMEngine for Anthropic {
             }));
         }
 
-        let mut body = json!({
-            "model": self.model,
-            "max_tokens": 10000,
-            "messages": [{
-                "role": "user",
-                "content": self.content
-            }],
-            "tools": tool_definitions,
-            "tool_choice": {
-                "type": "auto",
-                // "disable_parallel_tool_use": true
-            }
-        });
+        let mut messages = self.initial_messages();
 
-        // Add thinking configuration if enabled
-        if self.thinking {
-            body["thinking"] = json!({
-                "type": "enabled",
-                "budget_tokens": self.thinking_tokens
+        for round in 0..MAX_TOOL_ROUNDS {
+            let mut body = json!({
+                "model": self.model,
+                "max_tokens": self.max_tokens,
+                "messages": messages,
+                "tools": tool_definitions,
+                "tool_choice": self.anthropic_tool_choice()
             });
-        }
 
-        debug!("Request: {}", body);
-
-        let raw_response = ureq::post(&format!("{}/v1/messages", self.base_url))
-            .header("x-api-key", self.api_key.as_str())
-            .header("anthropic-version", "2023-06-01")
-            .header("Content-Type", "application/json")
-            .send_json(&body);
+            if let Some(temperature) = self.temperature {
+                body["temperature"] = json!(temperature);
+            }
 
-        let mut response = match raw_response {
-            Ok(response) => response,
-            Err(err) => {
-                debug!("API Error: {}", err);
-                return Err(anyhow::anyhow!("API ERROR: {}", err));
+            // Add thinking configuration if enabled
+            if self.thinking {
+                body["thinking"] = json!({
+                    "type": "enabled",
+                    "budget_tokens": self.thinking_tokens
+                });
             }
-        };
-
-        // Read response body as string
-        let body_text = response.body_mut().read_to_string().unwrap();
-        let json: json = serde_json::from_str(&body_text).unwrap();
-        debug!("Response: {}", json);
-        let content_array = &json["content"];
-
-        // Loop through all content entries
-        for content_item in content_array.as_array().unwrap_or(&Vec::new()) {
-            let content_type = content_item["type"].as_str().unwrap_or("");
-
-            match content_type {
-                "tool_use" => {
-                    let function_name = content_item["name"].as_str().unwrap();
-                    let function_input = &content_item["input"];
-                    let tool = self.tools.iter_mut().find(|tool| tool.name == function_name);
-
-                    if let Some(tool) = tool {
-                        if let Some(callback) = &mut tool.callback {
-                            callback(function_input.clone());
-                            return Ok(());
+
+            debug!("Request: {}", body);
+
+            let headers = vec![
+                ("x-api-key", self.api_key.clone()),
+                ("anthropic-version", "2023-06-01".to_string()),
+                ("Content-Type", "application/json".to_string()),
+            ];
+            let body_text = post_json(format!("{}/v1/messages", self.base_url), headers, body, &self.cancel).inspect_err(|err| debug!("API Error: {}", err))?;
+            let json: json = serde_json::from_str(&body_text).map_err(|e| anyhow::anyhow!("Invalid JSON in response: {}", e))?;
+            debug!("Response: {}", json);
+            let content_array = &json["content"];
+
+            let mut tool_result = None;
+
+            // Loop through all content entries
+            for content_item in content_array.as_array().unwrap_or(&Vec::new()) {
+                let content_type = content_item["type"].as_str().unwrap_or("");
+
+                match content_type {
+                    "tool_use" => {
+                        let function_name = content_item["name"].as_str().unwrap();
+                        let function_input = &content_item["input"];
+                        let tool = self.tools.iter_mut().find(|tool| tool.name == function_name);
+
+                        if let Some(tool) = tool {
+                            if let Some(callback) = &mut tool.callback {
+                                match callback(function_input.clone()) {
+                                    Some(result) => {
+                                        tool_result = Some((content_item["id"].clone(), result));
+                                        break;
+                                    }
+                                    None => return Ok(()),
+                                }
+                            } else {
+                                return Err(anyhow::anyhow!("No callback registered for tool {}", function_name));
+                            }
                         } else {
-                            return Err(anyhow::anyhow!("No callback registered for tool {}", function_name));
+                            return Err(anyhow::anyhow!("No tool registered with name {}", function_name));
                         }
-                    } else {
-                        return Err(anyhow::anyhow!("No tool registered with name {}", function_name));
                     }
-                }
-                "thinking" => {
-                    if let Some(thinking) = content_item.get("thinking") {
-                        debug!("Thinking: {}", thinking);
+                    "thinking" => {
+                        if let Some(thinking) = content_item.get("thinking") {
+                            debug!("Thinking: {}", thinking);
+                        }
                     }
-                }
-                "text" => {
-                    if let Some(text) = content_item.get("text") {
-                        debug!("Text: {}", text);
+                    "text" => {
+                        if let Some(text) = content_item.get("text") {
+                            debug!("Text: {}", text);
+                        }
+                    }
+                    _ => {
+                        debug!("Unknown content type: {}", content_type);
                     }
                 }
-                _ => {
-                    debug!("Unknown content type: {}", content_type);
+            }
+
+            match tool_result {
+                Some((tool_use_id, result)) => {
+                    debug!("Sending tool result back to the model: {}", result);
+                    // Only the tool call we handled gets a result, so leave out any others
+                    let assistant_content = content_array
+                        .as_array()
+                        .unwrap_or(&Vec::new())
+                        .iter()
+                        .filter(|item| item["type"] != "tool_use" || item["id"] == tool_use_id)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    messages.push(json!({
+                        "role": "assistant",
+                        "content": assistant_content
+                    }));
+                    messages.push(json!({
+                        "role": "user",
+                        "content": [{
+                            "type": "tool_result",
+                            "tool_use_id": tool_use_id,
+                            "content": result
+                        }]
+                    }));
+                }
+                // After a tool result, a reply without a tool call is the model finishing its turn
+                None if round > 0 => {
+                    debug!("No further tool calls, ending the turn");
+                    return Ok(());
                 }
+                None => return Err(anyhow::anyhow!("No tool calls found in response")),
             }
         }
 
-        Err(anyhow::anyhow!("No tool calls found in response"))
+        Err(anyhow::anyhow!("Gave up after {} rounds of tool calls", MAX_TOOL_ROUNDS))
     }
 }
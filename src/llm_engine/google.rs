@@ -1,4 +1,5 @@
-use super::LLMEngine;
+use super::{post_json, tool_parameters, Example, LLMEngine, ToolCallback, ToolChoice, EXAMPLE_TOOL_RESULT, MAX_TOOL_ROUNDS};
+use crate::cancel::CancelToken;
 use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
 use anyhow::Result;
 use log::{debug, info};
@@ -8,7 +9,7 @@ use serde_json::Value as json;
 pub struct Tool {
     name: String,
     definition: json,
-    callback: Option<Box<dyn FnMut(json)>>,
+    callback: Option<ToolCallback>,
 }
 
 pub struct Google {
@@ -17,6 +18,11 @@ pub struct Google {
     api_key: String,
     tools: Vec<Tool>,
     content: Vec<json>,
+    examples: Vec<Example>,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    tool_choice: ToolChoice,
+    cancel: CancelToken,
 }
 
 impl Google {
@@ -24,10 +30,61 @@ impl Google {
         json!({
             "name": tool.definition["name"],
             "description": tool.definition["description"],
-            "parameters": tool.definition["parameters"],
+            "parameters": tool_parameters(&tool.definition),
         })
     }
 
+    fn image_content(base64_image: &str) -> json {
+        json!({
+            "inline_data": {
+                "mime_type": "image/png",
+                "data": base64_image,
+            }
+        })
+    }
+
+    /// Examples become user/model turns ahead of the real content; each example's
+    /// function response leads the following user turn
+    fn initial_contents(&self) -> Vec<json> {
+        let mut contents = Vec::new();
+        let mut pending_response: Option<json> = None;
+
+        for example in &self.examples {
+            let mut parts = pending_response.take().into_iter().collect::<Vec<_>>();
+            parts.push(Self::image_content(&example.base64_image));
+            contents.push(json!({ "role": "user", "parts": parts }));
+            contents.push(json!({
+                "role": "model",
+                "parts": [{
+                    "functionCall": {
+                        "name": example.tool_name,
+                        "args": example.arguments
+                    }
+                }]
+            }));
+            pending_response = Some(json!({
+                "functionResponse": {
+                    "name": example.tool_name,
+                    "response": { "content": EXAMPLE_TOOL_RESULT }
+                }
+            }));
+        }
+
+        let mut parts = pending_response.into_iter().collect::<Vec<_>>();
+        parts.extend(self.content.iter().cloned());
+        contents.push(json!({ "role": "user", "parts": parts }));
+        contents
+    }
+
+    fn google_function_calling_config(&self) -> json {
+        match &self.tool_choice {
+            ToolChoice::Auto => json!({ "mode": "AUTO" }),
+            ToolChoice::Any => json!({ "mode": "ANY" }),
+            ToolChoice::None => json!({ "mode": "NONE" }),
+            ToolChoice::Tool(name) => json!({ "mode": "ANY", "allowed_function_names": [name] }),
+        }
+    }
+
     pub fn add_content(&mut self, content: json) {
         self.content.push(content);
     }
@@ -38,6 +95,9 @@ impl LLMEngine for Google {
         let api_key = option_or_env(options, "api_key", "GOOGLE_API_KEY");
         let base_url = option_or_env_fallback(options, "base_url", "GOOGLE_BASE_URL", "https://generativelanguage.googleapis.com");
         let model = options.get("model").unwrap().to_string();
+        let max_tokens = options.get("max_tokens").and_then(|v| v.parse::<u32>().ok());
+        let temperature = options.get("temperature").and_then(|v| v.parse::<f32>().ok());
+        let tool_choice = ToolChoice::from_options(options, ToolChoice::Any);
 
         Self {
             model,
@@ -45,10 +105,15 @@ impl LLMEngine for Google {
             api_key,
             tools: Vec::new(),
             content: Vec::new(),
+            examples: Vec::new(),
+            max_tokens,
+            temperature,
+            tool_choice,
+            cancel: CancelToken::new(),
         }
     }
 
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>) {
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback) {
         self.tools.push(Tool {
             name: name.to_string(),
             definition,
@@ -63,70 +128,100 @@ impl LLMEngine for Google {
     }
 
     fn add_image_content(&mut self, base64_image: &str) {
-        self.add_content(json!({
-            "inline_data": {
-                "mime_type": "image/png",
-                "data": base64_image,
-            }
-        }));
+        self.add_content(Self::image_content(base64_image));
+    }
+
+    fn add_example(&mut self, example: Example) {
+        if self.tools.iter().any(|tool| tool.name == example.tool_name) {
+            self.examples.push(example);
+        } else {
+            debug!("Skipping example for unregistered tool {}", example.tool_name);
+        }
     }
 
     fn clear_content(&mut self) {
         self.content.clear();
     }
 
+    fn set_cancel_token(&mut self, cancel: CancelToken) {
+        self.cancel = cancel;
+    }
+
     fn execute(&mut self) -> Result<()> {
-        let body = json!({
-            "contents": [{
-                "role": "user",
-                "parts": self.content
-            }],
-            "tools": [{ "function_declarations": self.tools.iter().map(Self::google_tool_definition).collect::<Vec<_>>() }],
-            "tool_config": {
-                "function_calling_config": {
-                    "mode": "ANY"
+        let mut contents = self.initial_contents();
+
+        for round in 0..MAX_TOOL_ROUNDS {
+            let mut body = json!({
+                "contents": contents,
+                "tools": [{ "function_declarations": self.tools.iter().map(Self::google_tool_definition).collect::<Vec<_>>() }],
+                "tool_config": {
+                    "function_calling_config": self.google_function_calling_config()
                 }
-            }
-        });
+            });
 
-        // print body for debugging
-        debug!("Request: {}", body);
-        let raw_response = ureq::post(format!("{}/v1beta/models/{}:generateContent?key={}", self.base_url, self.model, self.api_key).as_str())
-            .header("Content-Type", "application/json")
-            .send_json(&body);
-
-        let mut response = match raw_response {
-            Ok(response) => response,
-            Err(err) => {
-                info!("API Error: {}", err);
-                return Err(anyhow::anyhow!("API ERROR: {}", err));
+            if let Some(max_tokens) = self.max_tokens {
+                body["generationConfig"]["maxOutputTokens"] = json!(max_tokens);
+            }
+            if let Some(temperature) = self.temperature {
+                body["generationConfig"]["temperature"] = json!(temperature);
             }
-        };
-
-        // Read response body as string
-        let body_text = response.body_mut().read_to_string().unwrap();
-        let json: json = serde_json::from_str(&body_text).unwrap();
-        debug!("Response: {}", json);
 
-        let tool_calls = &json["candidates"][0]["content"]["parts"];
+            // print body for debugging
+            debug!("Request: {}", body);
+            let url = format!("{}/v1beta/models/{}:generateContent?key={}", self.base_url, self.model, self.api_key);
+            let headers = vec![("Content-Type", "application/json".to_string())];
+            let body_text = post_json(url, headers, body, &self.cancel).inspect_err(|err| info!("API Error: {}", err))?;
+            let json: json = serde_json::from_str(&body_text).map_err(|e| anyhow::anyhow!("Invalid JSON in response: {}", e))?;
+            debug!("Response: {}", json);
+
+            // With tool_choice "auto" the model may say something before calling a tool
+            let parts = json["candidates"][0]["content"]["parts"].as_array();
+            let Some(tool_call) = parts.into_iter().flatten().find(|part| part["functionCall"].is_object()) else {
+                // After a tool result, a reply without a tool call is the model finishing its turn
+                if round > 0 {
+                    debug!("No further tool calls, ending the turn");
+                    return Ok(());
+                }
+                return Err(anyhow::anyhow!("No tool calls found in response"));
+            };
 
-        if let Some(tool_call) = tool_calls.get(0) {
-            let function_name = tool_call["functionCall"]["name"].as_str().unwrap();
+            let function_name = tool_call["functionCall"]["name"]
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("Tool call without a function name in response"))?;
             let function_input = &tool_call["functionCall"]["args"];
             let tool = self.tools.iter_mut().find(|tool| tool.name == function_name);
 
-            if let Some(tool) = tool {
+            let result = if let Some(tool) = tool {
                 if let Some(callback) = &mut tool.callback {
-                    callback(function_input.clone());
-                    Ok(())
+                    callback(function_input.clone())
                 } else {
-                    Err(anyhow::anyhow!("No callback registered for tool {}", function_name))
+                    return Err(anyhow::anyhow!("No callback registered for tool {}", function_name));
                 }
             } else {
-                Err(anyhow::anyhow!("No tool registered with name {}", function_name))
+                return Err(anyhow::anyhow!("No tool registered with name {}", function_name));
+            };
+
+            match result {
+                Some(result) => {
+                    debug!("Sending tool result back to the model: {}", result);
+                    contents.push(json!({
+                        "role": "model",
+                        "parts": [tool_call]
+                    }));
+                    contents.push(json!({
+                        "role": "user",
+                        "parts": [{
+                            "functionResponse": {
+                                "name": function_name,
+                                "response": { "content": result }
+                            }
+                        }]
+                    }));
+                }
+                None => return Ok(()),
             }
-        } else {
-            Err(anyhow::anyhow!("No tool calls found in response"))
         }
+
+        Err(anyhow::anyhow!("Gave up after {} rounds of tool calls", MAX_TOOL_ROUNDS))
     }
 }
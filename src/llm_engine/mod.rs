@@ -3,16 +3,104 @@ pub mod google;
 pub mod openai;
 
 use anyhow::Result;
+use serde_json::json;
 use serde_json::Value as json;
 use std::collections::HashMap;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::cancel::CancelToken;
+
+/// How many times the model may be re-invoked with tool results before we give up on a turn
+pub const MAX_TOOL_ROUNDS: usize = 10;
+
+/// Called with the model's tool arguments. Returning `None` ends the turn;
+/// returning `Some(result)` sends the result back to the model and continues.
+pub type ToolCallback = Box<dyn FnMut(json) -> Option<String>>;
+
+/// A worked example shown to the model before the real screenshot: this input should produce this tool call
+#[derive(Debug, Clone)]
+pub struct Example {
+    pub base64_image: String,
+    pub tool_name: String,
+    pub arguments: json,
+}
+
+/// What the model is told after each example tool call
+pub const EXAMPLE_TOOL_RESULT: &str = "Done.";
 
 pub trait LLMEngine {
     fn new(options: &HashMap<String, String>) -> Self
     where
         Self: Sized;
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>);
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback);
     fn add_text_content(&mut self, text: &str);
     fn add_image_content(&mut self, base64_image: &str);
+    /// Examples are only kept if their tool is registered, so register tools first
+    fn add_example(&mut self, example: Example);
     fn clear_content(&mut self);
+    /// `execute` gives up with a `Cancelled` error once this token is cancelled
+    fn set_cancel_token(&mut self, cancel: CancelToken);
     fn execute(&mut self) -> Result<()>;
 }
+
+/// Which tool the model must call, from the "tool_choice" engine option
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolChoice {
+    /// The model may answer without calling a tool
+    Auto,
+    /// The model must call some tool
+    Any,
+    /// The model must not call a tool
+    None,
+    /// The model must call this specific tool
+    Tool(String),
+}
+
+impl ToolChoice {
+    pub fn from_options(options: &HashMap<String, String>, default: ToolChoice) -> Self {
+        match options.get("tool_choice").map(|choice| choice.as_str()) {
+            None => default,
+            Some("auto") => ToolChoice::Auto,
+            Some("any") | Some("required") => ToolChoice::Any,
+            Some("none") => ToolChoice::None,
+            Some(tool_name) => ToolChoice::Tool(tool_name.to_string()),
+        }
+    }
+}
+
+/// Tool definitions without parameters (like external commands) still need an empty schema
+pub fn tool_parameters(definition: &json) -> json {
+    match definition.get("parameters") {
+        Some(parameters) if !parameters.is_null() => parameters.clone(),
+        _ => json!({ "type": "object", "properties": {} }),
+    }
+}
+
+/// POST a JSON body and return the response text. The request runs on its own thread so that
+/// a cancelled interaction doesn't have to sit through a slow model response.
+pub fn post_json(url: String, headers: Vec<(&'static str, String)>, body: json, cancel: &CancelToken) -> Result<String> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        let mut request = ureq::post(&url);
+        for (name, value) in &headers {
+            request = request.header(*name, value);
+        }
+        let result = request
+            .send_json(&body)
+            .and_then(|mut response| response.body_mut().read_to_string())
+            .map_err(|err| err.to_string());
+        // Nobody is listening any more if the request was cancelled
+        let _ = sender.send(result);
+    });
+
+    loop {
+        cancel.check()?;
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(Ok(body_text)) => return Ok(body_text),
+            Ok(Err(err)) => return Err(anyhow::anyhow!("API ERROR: {}", err)),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Err(anyhow::anyhow!("API request thread stopped")),
+        }
+    }
+}
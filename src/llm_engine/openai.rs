@@ -1,4 +1,5 @@
-use super::LLMEngine;
+use super::{post_json, tool_parameters, Example, LLMEngine, ToolCallback, ToolChoice, EXAMPLE_TOOL_RESULT, MAX_TOOL_ROUNDS};
+use crate::cancel::CancelToken;
 use crate::util::{option_or_env, option_or_env_fallback, OptionMap};
 use anyhow::Result;
 use log::{debug, info};
@@ -8,7 +9,7 @@ use serde_json::Value as json;
 pub struct Tool {
     name: String,
     definition: json,
-    callback: Option<Box<dyn FnMut(json)>>,
+    callback: Option<ToolCallback>,
 }
 
 pub struct OpenAI {
@@ -17,6 +18,11 @@ pub struct OpenAI {
     api_key: String,
     tools: Vec<Tool>,
     content: Vec<json>,
+    examples: Vec<Example>,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    tool_choice: ToolChoice,
+    cancel: CancelToken,
 }
 
 impl OpenAI {
@@ -26,11 +32,64 @@ impl OpenAI {
                 "function": {
             "name": tool.definition["name"],
             "description": tool.definition["description"],
-            "parameters": tool.definition["parameters"],
+            "parameters": tool_parameters(&tool.definition),
                 }
         })
     }
 
+    fn image_content(base64_image: &str) -> json {
+        json!({
+            "type": "image_url",
+            "image_url": {
+                "url": format!("data:image/png;base64,{}", base64_image)
+            }
+        })
+    }
+
+    /// Examples become user / assistant tool call / tool result turns ahead of the real content
+    fn initial_messages(&self) -> Vec<json> {
+        let mut messages = Vec::new();
+
+        for (i, example) in self.examples.iter().enumerate() {
+            let tool_call_id = format!("example_{}", i);
+            messages.push(json!({
+                "role": "user",
+                "content": [Self::image_content(&example.base64_image)]
+            }));
+            messages.push(json!({
+                "role": "assistant",
+                "tool_calls": [{
+                    "id": tool_call_id,
+                    "type": "function",
+                    "function": {
+                        "name": example.tool_name,
+                        "arguments": example.arguments.to_string()
+                    }
+                }]
+            }));
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": tool_call_id,
+                "content": EXAMPLE_TOOL_RESULT
+            }));
+        }
+
+        messages.push(json!({
+            "role": "user",
+            "content": self.content
+        }));
+        messages
+    }
+
+    fn openai_tool_choice(&self) -> json {
+        match &self.tool_choice {
+            ToolChoice::Auto => json!("auto"),
+            ToolChoice::Any => json!("required"),
+            ToolChoice::None => json!("none"),
+            ToolChoice::Tool(name) => json!({ "type": "function", "function": { "name": name } }),
+        }
+    }
+
     pub fn add_content(&mut self, content: json) {
         self.content.push(content);
     }
@@ -41,6 +100,9 @@ impl LLMEngine for OpenAI {
         let api_key = option_or_env(options, "api_key", "OPENAI_API_KEY");
         let base_url = option_or_env_fallback(options, "base_url", "OPENAI_BASE_URL", "https://api.openai.com");
         let model = options.get("model").unwrap().to_string();
+        let max_tokens = options.get("max_tokens").and_then(|v| v.parse::<u32>().ok());
+        let temperature = options.get("temperature").and_then(|v| v.parse::<f32>().ok());
+        let tool_choice = ToolChoice::from_options(options, ToolChoice::Any);
 
         Self {
             model,
@@ -48,10 +110,15 @@ impl LLMEngine for OpenAI {
             api_key,
             tools: Vec::new(),
             content: Vec::new(),
+            examples: Vec::new(),
+            max_tokens,
+            temperature,
+            tool_choice,
+            cancel: CancelToken::new(),
         }
     }
 
-    fn register_tool(&mut self, name: &str, definition: json, callback: Box<dyn FnMut(json)>) {
+    fn register_tool(&mut self, name: &str, definition: json, callback: ToolCallback) {
         self.tools.push(Tool {
             name: name.to_string(),
             definition,
@@ -67,70 +134,100 @@ impl LLMEngine for OpenAI {
     }
 
     fn add_image_content(&mut self, base64_image: &str) {
-        self.add_content(json!({
-            "type": "image_url",
-            "image_url": {
-                "url": format!("data:image/png;base64,{}", base64_image)
-            }
-        }));
+        self.add_content(Self::image_content(base64_image));
+    }
+
+    fn add_example(&mut self, example: Example) {
+        if self.tools.iter().any(|tool| tool.name == example.tool_name) {
+            self.examples.push(example);
+        } else {
+            debug!("Skipping example for unregistered tool {}", example.tool_name);
+        }
     }
 
     fn clear_content(&mut self) {
         self.content.clear();
     }
 
-    fn execute(&mut self) -> Result<()> {
-        let body = json!({
-            "model": self.model,
-            "messages": [{
-                "role": "user",
-                "content": self.content
-            }],
-            "tools": self.tools.iter().map(Self::openai_tool_definition).collect::<Vec<_>>(),
-            "tool_choice": "required",
-            "parallel_tool_calls": false
-        });
+    fn set_cancel_token(&mut self, cancel: CancelToken) {
+        self.cancel = cancel;
+    }
 
-        // print body for debugging
-        debug!("Request: {}", body);
-        let raw_response = ureq::post(format!("{}/v1/chat/completions", self.base_url).as_str())
-            .header("Authorization", &format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .send_json(&body);
-
-        let mut response = match raw_response {
-            Ok(response) => response,
-            Err(err) => {
-                info!("API Error: {}", err);
-                return Err(anyhow::anyhow!("API ERROR: {}", err));
+    fn execute(&mut self) -> Result<()> {
+        let mut messages = self.initial_messages();
+
+        for round in 0..MAX_TOOL_ROUNDS {
+            let mut body = json!({
+                "model": self.model,
+                "messages": messages,
+                "tools": self.tools.iter().map(Self::openai_tool_definition).collect::<Vec<_>>(),
+                "tool_choice": self.openai_tool_choice(),
+                "parallel_tool_calls": false
+            });
+
+            if let Some(max_tokens) = self.max_tokens {
+                body["max_completion_tokens"] = json!(max_tokens);
+            }
+            if let Some(temperature) = self.temperature {
+                body["temperature"] = json!(temperature);
             }
-        };
-
-        // Read response body as string
-        let body_text = response.body_mut().read_to_string().unwrap();
-        let json: json = serde_json::from_str(&body_text).unwrap();
-        debug!("Response: {}", json);
 
-        let tool_calls = &json["choices"][0]["message"]["tool_calls"];
+            // print body for debugging
+            debug!("Request: {}", body);
+            let headers = vec![
+                ("Authorization", format!("Bearer {}", self.api_key)),
+                ("Content-Type", "application/json".to_string()),
+            ];
+            let body_text =
+                post_json(format!("{}/v1/chat/completions", self.base_url), headers, body, &self.cancel).inspect_err(|err| info!("API Error: {}", err))?;
+            let json: json = serde_json::from_str(&body_text).map_err(|e| anyhow::anyhow!("Invalid JSON in response: {}", e))?;
+            debug!("Response: {}", json);
+
+            let message = &json["choices"][0]["message"];
+            let tool_calls = &message["tool_calls"];
+
+            let Some(tool_call) = tool_calls.get(0) else {
+                // After a tool result, a reply without a tool call is the model finishing its turn
+                if round > 0 {
+                    debug!("No further tool calls, ending the turn");
+                    return Ok(());
+                }
+                return Err(anyhow::anyhow!("No tool calls found in response"));
+            };
 
-        if let Some(tool_call) = tool_calls.get(0) {
             let function_name = tool_call["function"]["name"].as_str().unwrap();
             let function_input_raw = tool_call["function"]["arguments"].as_str().unwrap();
             let function_input = serde_json::from_str::<json>(function_input_raw).unwrap();
             let tool = self.tools.iter_mut().find(|tool| tool.name == function_name);
 
-            if let Some(tool) = tool {
+            let result = if let Some(tool) = tool {
                 if let Some(callback) = &mut tool.callback {
-                    callback(function_input.clone());
-                    Ok(())
+                    callback(function_input.clone())
                 } else {
-                    Err(anyhow::anyhow!("No callback registered for tool {}", function_name))
+                    return Err(anyhow::anyhow!("No callback registered for tool {}", function_name));
                 }
             } else {
-                Err(anyhow::anyhow!("No tool registered with name {}", function_name))
+                return Err(anyhow::anyhow!("No tool registered with name {}", function_name));
+            };
+
+            match result {
+                Some(result) => {
+                    debug!("Sending tool result back to the model: {}", result);
+                    messages.push(json!({
+                        "role": "assistant",
+                        "content": message["content"],
+                        "tool_calls": [tool_call]
+                    }));
+                    messages.push(json!({
+                        "role": "tool",
+                        "tool_call_id": tool_call["id"],
+                        "content": result
+                    }));
+                }
+                None => return Ok(()),
             }
-        } else {
-            Err(anyhow::anyhow!("No tool calls found in response"))
         }
+
+        Err(anyhow::anyhow!("Gave up after {} rounds of tool calls", MAX_TOOL_ROUNDS))
     }
 }
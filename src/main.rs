@@ -1,6 +1,7 @@
 use anyhow::Result;
 use base64::prelude::*;
-use clap::Parser;
+use chrono::Local;
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
 use log::{debug, info};
 use serde::Serialize;
@@ -8,24 +9,48 @@ use serde_json::Value as json;
 use std::sync::{Arc, Mutex};
 
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use ghostwriter::{
+    cancel::{is_cancelled, CancelToken},
     config::Config,
-    embedded_assets::load_config,
+    confirm::{check_trigger_zones, confirm_on_screen},
+    device::DeviceModel,
+    external_command::ExternalCommand,
+    handwriting::{handwriting_strokes, Handwriting},
+    history::InteractionHistory,
+    ink::{describe_ink, describe_ink_bounds, InkFormat, InkLog},
+    ink_limit::{InkLimitAction, InkLimits},
     keyboard::Keyboard,
-    llm_engine::{anthropic::Anthropic, google::Google, openai::OpenAI, LLMEngine},
-    pen::Pen,
+    llm_engine::{anthropic::Anthropic, google::Google, openai::OpenAI, LLMEngine, ToolCallback},
+    pen::{DrawProgress, DrawSpeed, Pen, ProgressCallback, Stroke},
+    pen_input::{PenInput, PenStroke},
+    pen_symbol::{PenSymbol, PenSymbolRecognizer},
+    prompt::{load_tool_definition, render_json_template, render_template, Prompt, TemplateVariables},
+    router::select_route,
     screenshot::Screenshot,
     segmenter::analyze_image,
-    touch::{Touch, TriggerCorner},
-    util::{setup_uinput, svg_to_bitmap, write_bitmap_to_file, OptionMap},
+    svg_paths::{svg_to_strokes, Hatch, HatchStyle, Polyline},
+    touch::{Touch, TouchAction, TriggerZone},
+    trace::{replay_trace, TraceRecorder},
+    util::{setup_uinput, svg_to_bitmap, svg_to_bitmap_scaled, write_bitmap_to_file, OptionMap},
+    xochitl::current_document,
 };
 
 // Output dimensions remain the same for both devices
 const VIRTUAL_WIDTH: u32 = 768;
 const VIRTUAL_HEIGHT: u32 = 1024;
 
+// How many past interactions the {{history}} template variable remembers
+const MAX_HISTORY_ENTRIES: usize = 5;
+
+// How long to wait on the touchscreen before checking the pen, and the other way around
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Capital height (px) and slant (degrees) for draw_handwriting when the model doesn't say
+const DEFAULT_HANDWRITING_SIZE: f64 = 24.0;
+const DEFAULT_HANDWRITING_SLANT: f64 = 10.0;
+
 #[derive(Parser, Serialize)]
 #[command(author, version)]
 #[command(about = "Vision-LLM Agent for the reMarkable2")]
@@ -69,6 +94,51 @@ pub struct Args {
     #[arg(long)]
     no_svg: bool,
 
+    /// Draw SVGs dot by dot from a bitmap instead of following their paths
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    svg_raster: bool,
+
+    /// How to shade filled SVG shapes (none, parallel, cross, contour)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hatch_style: Option<String>,
+
+    /// Gap between hatching lines for black fills, in pixels; lighter fills get wider gaps
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hatch_spacing: Option<f32>,
+
+    /// Leave out every other row of big filled areas when drawing bitmaps
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    skip_scanlines: bool,
+
+    /// Render bitmaps at this many times the screen resolution before drawing them
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raster_scale: Option<u32>,
+
+    /// Most virtual pixels the pen may draw for one drawing (0 for no limit)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_ink_pixels: Option<u64>,
+
+    /// Most input events the pen may send for one drawing (0 for no limit)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_pen_events: Option<u64>,
+
+    /// What to do with drawings over the ink limits (refuse, outline, scale)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ink_limit_action: Option<String>,
+
+    /// How fast the pen draws (fast, normal, careful); use careful if strokes come out broken
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    draw_speed: Option<String>,
+
     /// Disable keyboard
     #[arg(long)]
     no_keyboard: bool,
@@ -121,6 +191,36 @@ pub struct Args {
     #[arg(long, default_value = "5000")]
     thinking_tokens: u32,
 
+    /// Set the maximum number of tokens the model may respond with
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+
+    /// Set the model temperature
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+
+    /// Which tool the model must call: auto, any, none, or a tool name
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<String>,
+
+    /// Pick the prompt with a first, cheap model call based on the page content (routes come from the config file)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    router: bool,
+
+    /// Sets the engine for the router call
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    router_engine: Option<String>,
+
+    /// Sets the model for the router call (default: same as --model)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    router_model: Option<String>,
+
     /// Set the log level. Try 'debug' or 'trace'
     #[arg(long, default_value = "info")]
     log_level: String,
@@ -129,9 +229,77 @@ pub struct Args {
     #[arg(long, default_value = "UR")]
     trigger_corner: String,
 
+    /// Send the pen strokes drawn since the last interaction to the model as svg or json
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ink_context: Option<String>,
+
+    /// Trigger automatically once the pen has been idle this many seconds after new ink
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idle_trigger_secs: Option<u64>,
+
+    /// Prompt for the idle trigger
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idle_prompt: Option<String>,
+
+    /// Let touches through while the pen is near the screen, and large (palm-sized) contacts
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    no_palm_rejection: bool,
+
+    /// Pen device to use instead of looking for one, such as /dev/input/event1
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pen_device: Option<String>,
+
+    /// Touchscreen device to use instead of looking for one, such as /dev/input/event2
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    touch_device: Option<String>,
+
+    /// Read touches from an evtest log instead of the touchscreen, for testing gestures
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replay_touch_log: Option<String>,
+
+    /// Record every pen and touch event ghostwriter sends to this trace file
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record_trace: Option<String>,
+
+    /// Allow an external_command tool to run this executable (repeatable)
+    #[arg(long = "tool-allow")]
+    #[serde(rename = "tool_allowed_commands", skip_serializing_if = "Vec::is_empty")]
+    tool_allow: Vec<String>,
+
+    /// Working directory that external_command tools run in (default: current directory)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_working_dir: Option<String>,
+
+    /// Log external_command tools instead of running them
+    #[arg(long)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    tool_dry_run: bool,
+
     /// Save current configuration to ~/.ghostwriter.toml and exit
     #[arg(long)]
     save_config: bool,
+
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Play a trace recorded with --record-trace back onto the pen and touchscreen, then exit
+    ReplayTrace {
+        /// The trace file
+        trace: String,
+    },
 }
 
 fn main() -> Result<()> {
@@ -143,6 +311,11 @@ fn main() -> Result<()> {
         .format_timestamp_millis()
         .init();
 
+    if let Some(Command::ReplayTrace { trace }) = &args.command {
+        let config = Config::load(&args)?;
+        return replay_trace(trace, config.pen_device.as_deref(), config.touch_device.as_deref());
+    }
+
     setup_uinput()?;
 
     ghostwriter(&args)
@@ -165,24 +338,98 @@ fn draw_text(text: &str, keyboard: &mut Keyboard) -> Result<()> {
     // keyboard.progress(".")?;
     keyboard.progress_end()?;
     keyboard.key_cmd_body()?;
-    keyboard.string_to_keypresses(text)?;
+    keyboard.type_text(text)?;
     // keyboard.string_to_keypresses("\n\n")?;
     Ok(())
 }
 
-fn draw_svg(svg_data: &str, keyboard: &mut Keyboard, pen: &mut Pen, save_bitmap: Option<&String>, no_draw: bool) -> Result<()> {
+/// How draw_svg turns an SVG into pen strokes
+#[derive(Clone, Copy)]
+struct SvgOptions {
+    raster: bool,
+    hatch: Hatch,
+    skip_scanlines: bool,
+    raster_scale: u32,
+}
+
+fn draw_svg(
+    svg_data: &str,
+    keyboard: &mut Keyboard,
+    pen: &mut Pen,
+    save_bitmap: Option<&String>,
+    no_draw: bool,
+    options: SvgOptions,
+    limits: InkLimits,
+) -> Result<()> {
     info!("Drawing SVG to the screen.");
     keyboard.progress_end()?;
-    let bitmap = svg_to_bitmap(svg_data, VIRTUAL_WIDTH, VIRTUAL_HEIGHT)?;
+    let strokes = if options.raster {
+        Vec::new()
+    } else {
+        svg_to_strokes(svg_data, VIRTUAL_WIDTH, VIRTUAL_HEIGHT, options.hatch)?
+    };
     if let Some(save_bitmap) = save_bitmap {
-        write_bitmap_to_file(&bitmap, save_bitmap)?;
+        write_bitmap_to_file(&svg_to_bitmap(svg_data, VIRTUAL_WIDTH, VIRTUAL_HEIGHT)?, save_bitmap)?;
     }
-    if !no_draw {
-        pen.draw_bitmap(&bitmap)?;
+    if no_draw {
+        return Ok(());
+    }
+
+    if strokes.is_empty() {
+        if !options.raster {
+            info!("No paths to draw in the SVG, drawing it as a bitmap");
+        }
+        let bitmap = svg_to_bitmap_scaled(svg_data, VIRTUAL_WIDTH, VIRTUAL_HEIGHT, options.raster_scale)?;
+        let bitmap = limits.fit_bitmap(bitmap, options.skip_scanlines, options.raster_scale)?;
+        pen.draw_bitmap(&bitmap, options.skip_scanlines, options.raster_scale)?;
+    } else {
+        let outline = Hatch {
+            style: HatchStyle::None,
+            ..options.hatch
+        };
+        let strokes = limits.fit_strokes(strokes, || svg_to_strokes(svg_data, VIRTUAL_WIDTH, VIRTUAL_HEIGHT, outline))?;
+        pen.draw_strokes(strokes)?;
     }
     Ok(())
 }
 
+fn draw_handwriting(letters: Vec<Polyline>, options: &Handwriting, keyboard: &mut Keyboard, pen: &mut Pen, limits: InkLimits) -> Result<()> {
+    info!("Writing text by hand at ({}, {}).", options.x, options.y);
+    keyboard.progress_end()?;
+    let strokes: Vec<Stroke> = letters.into_iter().map(Stroke::from).collect();
+    // Letters are already just outlines
+    let outline = strokes.clone();
+    pen.draw_strokes(limits.fit_strokes(strokes, || Ok(outline))?)
+}
+
+/// Log how far a long drawing has got, every tenth of the way
+fn log_draw_progress() -> ProgressCallback {
+    let mut last_tenth = 0;
+    Box::new(move |progress: DrawProgress| {
+        let tenth = progress.strokes_done * 10 / progress.strokes_total.max(1);
+        if progress.strokes_done == 1 {
+            last_tenth = 0;
+        }
+        if tenth > last_tenth {
+            last_tenth = tenth;
+            info!(
+                "Drawn {}/{} strokes ({}%) in {:.1}s of an estimated {:.1}s",
+                progress.strokes_done,
+                progress.strokes_total,
+                tenth * 10,
+                progress.elapsed.as_secs_f32(),
+                progress.estimated.as_secs_f32()
+            );
+        }
+    })
+}
+
+fn erase_region(region: (i32, i32, i32, i32), keyboard: &mut Keyboard, pen: &mut Pen) -> Result<()> {
+    info!("Erasing the region {:?}.", region);
+    keyboard.progress_end()?;
+    pen.erase_rect(region)
+}
+
 fn determine_engine_name(engine_arg: &Option<String>, model: &str) -> Result<String> {
     if let Some(engine) = engine_arg {
         return Ok(engine.clone());
@@ -202,87 +449,171 @@ fn determine_engine_name(engine_arg: &Option<String>, model: &str) -> Result<Str
     }
 }
 
-fn create_engine(engine_name: &str, engine_options: &OptionMap) -> Result<Box<dyn LLMEngine>> {
-    match engine_name {
-        "openai" => Ok(Box::new(OpenAI::new(engine_options))),
-        "anthropic" => Ok(Box::new(Anthropic::new(engine_options))),
-        "google" => Ok(Box::new(Google::new(engine_options))),
-        _ => Err(anyhow::anyhow!(
-            "Unknown engine '{}'. Supported engines: openai, anthropic, google",
-            engine_name
-        )),
-    }
+/// Load a prompt with its examples and check its engine settings, returning the engine it uses
+fn check_prompt(config: &Config, filename: &str) -> Result<String> {
+    let prompt = Prompt::load(filename)?;
+    prompt.load_examples()?;
+    let (engine_name, _) = engine_options(config, &prompt)?;
+    Ok(engine_name)
 }
 
-fn ghostwriter(args: &Args) -> Result<()> {
-    let config = Config::load(args)?;
-
-    // Handle --save-config option
-    if args.save_config {
-        config.save()?;
-        println!("Configuration saved to {:?}", Config::config_path()?);
-        return Ok(());
-    }
-
-    let trigger_corner = TriggerCorner::from_string(&config.trigger_corner)?;
-    let keyboard = shared!(Keyboard::new(config.no_draw || config.no_keyboard, config.no_draw_progress,));
-    let pen = shared!(Pen::new(config.no_draw));
-    let touch = shared!(Touch::new(config.no_draw, trigger_corner));
-
-    // Give time for the virtual keyboard to be plugged in
-    sleep(Duration::from_millis(1000));
-
-    lock!(touch).tap_middle_bottom()?;
-    sleep(Duration::from_millis(1000));
-
-    lock!(keyboard).progress("Keyboard loaded...")?;
-
+/// Merge the prompt's preferred engine settings over the config to get the engine name and options
+fn engine_options(config: &Config, prompt: &Prompt) -> Result<(String, OptionMap)> {
     let mut engine_options = OptionMap::new();
 
-    let model = config.model.clone();
+    let model = prompt.model.clone().unwrap_or_else(|| config.model.clone());
     engine_options.insert("model".to_string(), model.clone());
     debug!("Model: {}", model);
 
-    let engine_name = determine_engine_name(&config.engine, &model)?;
+    // A prompt that picks its own model without naming an engine gets the engine guessed from that model
+    let engine_arg = if prompt.engine.is_some() || prompt.model.is_some() {
+        &prompt.engine
+    } else {
+        &config.engine
+    };
+    let engine_name = determine_engine_name(engine_arg, &model)?;
     debug!("Engine: {}", engine_name);
 
-    if config.engine_base_url.is_some() {
-        debug!("Engine base URL: {}", config.engine_base_url.clone().unwrap());
-        engine_options.insert("base_url".to_string(), config.engine_base_url.clone().unwrap());
+    // The configured URL and key belong to the configured engine, so don't hand them to a different one
+    let config_engine_name = determine_engine_name(&config.engine, &config.model).ok();
+
+    // The configured model only makes sense on the configured engine
+    if prompt.model.is_none() && config_engine_name.as_deref() != Some(engine_name.as_str()) {
+        return Err(anyhow::anyhow!(
+            "Prompt '{}' switches to the {} engine but doesn't say which model; set 'model' in the prompt too",
+            prompt.filename,
+            engine_name
+        ));
     }
-    if config.engine_api_key.is_some() {
-        debug!("Using API key from CLI args");
-        engine_options.insert("api_key".to_string(), config.engine_api_key.clone().unwrap());
+    if config_engine_name.as_deref() == Some(engine_name.as_str()) {
+        if let Some(base_url) = &config.engine_base_url {
+            debug!("Engine base URL: {}", base_url);
+            engine_options.insert("base_url".to_string(), base_url.clone());
+        }
+        if let Some(api_key) = &config.engine_api_key {
+            debug!("Using API key from CLI args");
+            engine_options.insert("api_key".to_string(), api_key.clone());
+        }
     }
 
-    if config.web_search {
+    if prompt.web_search.unwrap_or(config.web_search) {
         debug!("Web search tool enabled");
         engine_options.insert("web_search".to_string(), "true".to_string());
     }
 
-    if config.thinking {
-        debug!("Thinking enabled with budget: {}", config.thinking_tokens);
+    if prompt.thinking.unwrap_or(config.thinking) {
+        let thinking_tokens = prompt.thinking_tokens.unwrap_or(config.thinking_tokens);
+        debug!("Thinking enabled with budget: {}", thinking_tokens);
         engine_options.insert("thinking".to_string(), "true".to_string());
-        engine_options.insert("thinking_tokens".to_string(), config.thinking_tokens.to_string());
+        engine_options.insert("thinking_tokens".to_string(), thinking_tokens.to_string());
+    }
+
+    if let Some(max_tokens) = prompt.max_tokens.or(config.max_tokens) {
+        debug!("Max tokens: {}", max_tokens);
+        engine_options.insert("max_tokens".to_string(), max_tokens.to_string());
+    }
+
+    if let Some(temperature) = prompt.temperature.or(config.temperature) {
+        debug!("Temperature: {}", temperature);
+        engine_options.insert("temperature".to_string(), temperature.to_string());
+    }
+
+    if let Some(tool_choice) = prompt.tool_choice.as_ref().or(config.tool_choice.as_ref()) {
+        debug!("Tool choice: {}", tool_choice);
+        engine_options.insert("tool_choice".to_string(), tool_choice.clone());
     }
 
-    let mut engine = create_engine(&engine_name, &engine_options)?;
+    Ok((engine_name, engine_options))
+}
+
+/// Run the router model over the screenshot and return the prompt file for the page
+fn route_prompt(config: &Config, base64_image: &str, cancel: &CancelToken) -> Result<String> {
+    let router_prompt = Prompt {
+        engine: config.router_engine.clone(),
+        model: config.router_model.clone(),
+        thinking: Some(false),
+        web_search: Some(false),
+        tool_choice: Some("select_route".to_string()),
+        ..Default::default()
+    };
+    let (engine_name, engine_options) = engine_options(config, &router_prompt)?;
+    let mut engine = create_engine(&engine_name, &engine_options, cancel)?;
+    let route = select_route(engine.as_mut(), &config.router_routes, base64_image)?;
+    Ok(route.prompt.clone())
+}
+
+fn create_engine(engine_name: &str, engine_options: &OptionMap, cancel: &CancelToken) -> Result<Box<dyn LLMEngine>> {
+    let mut engine: Box<dyn LLMEngine> = match engine_name {
+        "openai" => Box::new(OpenAI::new(engine_options)),
+        "anthropic" => Box::new(Anthropic::new(engine_options)),
+        "google" => Box::new(Google::new(engine_options)),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Unknown engine '{}'. Supported engines: openai, anthropic, google",
+                engine_name
+            ))
+        }
+    };
+    engine.set_cancel_token(cancel.clone());
+    Ok(engine)
+}
+
+/// Devices and state shared between the main loop and the tool callbacks
+struct SharedState {
+    keyboard: Arc<Mutex<Keyboard>>,
+    pen: Arc<Mutex<Pen>>,
+    touch: Arc<Mutex<Touch>>,
+    history: Arc<Mutex<InteractionHistory>>,
+}
+
+fn register_tools(engine: &mut dyn LLMEngine, tool_names: &[String], config: &Config, shared: &SharedState, variables: &TemplateVariables) -> Result<()> {
+    for tool_name in tool_names {
+        if tool_name == "draw_svg" && config.no_svg {
+            debug!("Skipping draw_svg tool due to --no-svg");
+            continue;
+        }
+
+        let mut definition = load_tool_definition(tool_name)?;
+        render_json_template(&mut definition, variables);
+
+        let callback = if let Some(internal_command) = definition["internal_command"].as_str() {
+            internal_tool_callback(internal_command, config, shared)?
+        } else if definition["external_command"].is_string() {
+            external_tool_callback(&definition, config)?
+        } else {
+            return Err(anyhow::anyhow!(
+                "Tool '{}' needs either an 'internal_command' or an 'external_command'",
+                tool_name
+            ));
+        };
+
+        let callback = history_tool_callback(tool_name, callback, shared);
 
+        // Tools marked "confirm" wait for a tap on YES before they run
+        let callback = if definition["confirm"].as_bool() == Some(true) {
+            confirm_tool_callback(tool_name, callback, shared)
+        } else {
+            callback
+        };
+
+        debug!("Registering tool {}", tool_name);
+        engine.register_tool(tool_name, definition, callback);
+    }
+    Ok(())
+}
+
+fn internal_tool_callback(internal_command: &str, config: &Config, shared: &SharedState) -> Result<ToolCallback> {
     let output_file = config.output_file.clone();
     let no_draw = config.no_draw;
-    let keyboard_clone = Arc::clone(&keyboard);
+    let keyboard_clone = Arc::clone(&shared.keyboard);
 
-    let tool_config_draw_text = load_config("tool_draw_text.json");
-
-    engine.register_tool(
-        "draw_text",
-        serde_json::from_str::<serde_json::Value>(tool_config_draw_text.as_str())?,
-        Box::new(move |arguments: json| {
+    match internal_command {
+        "draw_text" => Ok(Box::new(move |arguments: json| {
             let text = match arguments["text"].as_str() {
                 Some(t) => t,
                 None => {
                     log::error!("draw_text tool called without valid 'text' argument");
-                    return;
+                    return None;
                 }
             };
             if let Some(output_file) = &output_file {
@@ -292,30 +623,33 @@ fn ghostwriter(args: &Args) -> Result<()> {
             }
             if !no_draw {
                 // let mut keyboard = lock!(keyboard_clone);
-                if let Err(e) = draw_text(text, &mut lock!(keyboard_clone)) {
-                    log::error!("Failed to draw text: {}", e);
+                match draw_text(text, &mut lock!(keyboard_clone)) {
+                    Err(e) if is_cancelled(&e) => info!("Stopped typing text"),
+                    Err(e) => log::error!("Failed to draw text: {}", e),
+                    Ok(()) => {}
                 }
             }
-        }),
-    );
-
-    let output_file = config.output_file.clone();
-    let save_bitmap = config.save_bitmap.clone();
-    let no_draw = config.no_draw;
-    let keyboard_clone = Arc::clone(&keyboard);
-    let pen_clone = Arc::clone(&pen);
-
-    if !config.no_svg {
-        let tool_config_draw_svg = load_config("tool_draw_svg.json");
-        engine.register_tool(
-            "draw_svg",
-            serde_json::from_str::<serde_json::Value>(tool_config_draw_svg.as_str())?,
-            Box::new(move |arguments: json| {
+            None
+        })),
+        "draw_svg" => {
+            let save_bitmap = config.save_bitmap.clone();
+            let options = SvgOptions {
+                raster: config.svg_raster,
+                hatch: Hatch {
+                    style: HatchStyle::from_string(&config.hatch_style)?,
+                    spacing: config.hatch_spacing,
+                },
+                skip_scanlines: config.skip_scanlines,
+                raster_scale: config.raster_scale,
+            };
+            let limits = ink_limits(config)?;
+            let pen_clone = Arc::clone(&shared.pen);
+            Ok(Box::new(move |arguments: json| {
                 let svg_data = match arguments["svg"].as_str() {
                     Some(svg) => svg,
                     None => {
                         log::error!("draw_svg tool called without valid 'svg' argument");
-                        return;
+                        return None;
                     }
                 };
                 if let Some(output_file) = &output_file {
@@ -325,11 +659,373 @@ fn ghostwriter(args: &Args) -> Result<()> {
                 }
                 let mut keyboard = lock!(keyboard_clone);
                 let mut pen = lock!(pen_clone);
-                if let Err(e) = draw_svg(svg_data, &mut keyboard, &mut pen, save_bitmap.as_ref(), no_draw) {
-                    log::error!("Failed to draw SVG: {}", e);
+                match draw_svg(svg_data, &mut keyboard, &mut pen, save_bitmap.as_ref(), no_draw, options, limits) {
+                    Err(e) if is_cancelled(&e) => info!("Stopped drawing SVG"),
+                    Err(e) => log::error!("Failed to draw SVG: {}", e),
+                    Ok(()) => {}
                 }
-            }),
-        );
+                None
+            }))
+        }
+        "draw_handwriting" => {
+            let limits = ink_limits(config)?;
+            let pen_clone = Arc::clone(&shared.pen);
+            Ok(Box::new(move |arguments: json| {
+                let (Some(text), Some(x), Some(y)) = (arguments["text"].as_str(), arguments["x"].as_f64(), arguments["y"].as_f64()) else {
+                    log::error!("draw_handwriting tool called without valid 'text', 'x' and 'y' arguments");
+                    return Some("Nothing was written: draw_handwriting needs 'text', 'x' and 'y'".to_string());
+                };
+                let options = Handwriting {
+                    x: x as f32,
+                    y: y as f32,
+                    size: arguments["size"].as_f64().unwrap_or(DEFAULT_HANDWRITING_SIZE) as f32,
+                    max_width: arguments["max_width"].as_f64().map(|width| width as f32),
+                    slant: arguments["slant"].as_f64().unwrap_or(DEFAULT_HANDWRITING_SLANT) as f32,
+                };
+                // Hand a bad request back to the model so it can try again
+                let letters = match options.validate().and_then(|()| handwriting_strokes(text, &options)) {
+                    Ok(letters) => letters,
+                    Err(e) => {
+                        log::error!("draw_handwriting tool called with bad arguments: {}", e);
+                        return Some(format!("Nothing was written: {}", e));
+                    }
+                };
+                if let Some(output_file) = &output_file {
+                    if let Err(e) = std::fs::write(output_file, text) {
+                        log::error!("Failed to write output file: {}", e);
+                    }
+                }
+                if !no_draw {
+                    match draw_handwriting(letters, &options, &mut lock!(keyboard_clone), &mut lock!(pen_clone), limits) {
+                        Err(e) if is_cancelled(&e) => info!("Stopped writing by hand"),
+                        Err(e) => log::error!("Failed to write by hand: {}", e),
+                        Ok(()) => {}
+                    }
+                }
+                None
+            }))
+        }
+        "erase_region" => {
+            let pen_clone = Arc::clone(&shared.pen);
+            Ok(Box::new(move |arguments: json| {
+                let region = ["x", "y", "width", "height"].map(|key| arguments[key].as_f64());
+                let [Some(x), Some(y), Some(width), Some(height)] = region else {
+                    log::error!("erase_region tool called without valid 'x', 'y', 'width' and 'height' arguments");
+                    return Some("Nothing was erased: erase_region needs 'x', 'y', 'width' and 'height'".to_string());
+                };
+                let (x, y, width, height) = (x.round() as i32, y.round() as i32, width.round() as i32, height.round() as i32);
+                // The result goes back to the model, so it can draw in the cleared space
+                if !no_draw {
+                    match erase_region((x, y, width, height), &mut lock!(keyboard_clone), &mut lock!(pen_clone)) {
+                        Err(e) if is_cancelled(&e) => {
+                            info!("Stopped erasing");
+                            return None;
+                        }
+                        Err(e) => {
+                            log::error!("Failed to erase region: {}", e);
+                            return Some(format!("Failed to erase region {x},{y} {width}x{height}: {}", e));
+                        }
+                        Ok(()) => {}
+                    }
+                }
+                Some(format!("Erased region {x},{y} {width}x{height}"))
+            }))
+        }
+        _ => Err(anyhow::anyhow!("Unknown internal command '{}'", internal_command)),
+    }
+}
+
+fn ink_limits(config: &Config) -> Result<InkLimits> {
+    Ok(InkLimits {
+        max_ink_pixels: config.max_ink_pixels,
+        max_pen_events: config.max_pen_events,
+        action: InkLimitAction::from_string(&config.ink_limit_action)?,
+    })
+}
+
+fn external_tool_callback(definition: &json, config: &Config) -> Result<ToolCallback> {
+    let command = ExternalCommand::from_definition(definition, config)?;
+    // "loop" sends the command output back to the model, anything else ends the turn
+    let loop_back = definition["next_action"].as_str() == Some("loop");
+
+    Ok(Box::new(move |arguments: json| match command.run(&arguments) {
+        Ok(output) => {
+            if loop_back {
+                Some(output)
+            } else {
+                info!("Tool {} finished: {}", command.tool_name, output.trim());
+                None
+            }
+        }
+        Err(e) => {
+            log::error!("Tool {} failed: {}", command.tool_name, e);
+            loop_back.then(|| format!("Error: {}", e))
+        }
+    }))
+}
+
+fn history_tool_callback(tool_name: &str, mut callback: ToolCallback, shared: &SharedState) -> ToolCallback {
+    let tool_name = tool_name.to_string();
+    let history_clone = Arc::clone(&shared.history);
+
+    Box::new(move |arguments: json| {
+        lock!(history_clone).record(&tool_name, &arguments);
+        callback(arguments)
+    })
+}
+
+fn confirm_tool_callback(tool_name: &str, mut callback: ToolCallback, shared: &SharedState) -> ToolCallback {
+    let tool_name = tool_name.to_string();
+    let keyboard_clone = Arc::clone(&shared.keyboard);
+    let pen_clone = Arc::clone(&shared.pen);
+    let touch_clone = Arc::clone(&shared.touch);
+
+    Box::new(move |arguments: json| {
+        info!("Tool {} needs confirmation, arguments: {}", tool_name, arguments);
+        if let Err(e) = lock!(keyboard_clone).progress(&format!(" run {}? ", tool_name)) {
+            log::error!("Failed to show confirmation prompt: {}", e);
+        }
+        let confirmed = confirm_on_screen(&mut lock!(pen_clone), &mut lock!(touch_clone));
+        if let Err(e) = lock!(keyboard_clone).progress_end() {
+            log::error!("Failed to clear confirmation prompt: {}", e);
+        }
+        match confirmed {
+            Ok(true) => {
+                info!("Tool {} confirmed", tool_name);
+                callback(arguments)
+            }
+            Ok(false) => {
+                info!("Tool {} declined", tool_name);
+                None
+            }
+            Err(e) => {
+                log::error!("Could not confirm tool {}, not running it: {}", tool_name, e);
+                None
+            }
+        }
+    })
+}
+
+/// Everything a prompt or tool file can refer to as `{{name}}`
+fn template_variables(
+    trigger_name: &str,
+    segmentation_description: &str,
+    history: &InteractionHistory,
+    strokes: &[PenStroke],
+    ink_format: InkFormat,
+) -> TemplateVariables {
+    let now = Local::now();
+    let document = current_document();
+
+    let mut variables = TemplateVariables::new();
+    variables.insert("date".to_string(), now.format("%Y-%m-%d").to_string());
+    variables.insert("time".to_string(), now.format("%H:%M").to_string());
+    variables.insert("timezone".to_string(), std::env::var("TZ").unwrap_or_else(|_| now.format("UTC%:z").to_string()));
+    variables.insert("device_model".to_string(), DeviceModel::detect().name().to_string());
+    variables.insert("screen_width".to_string(), VIRTUAL_WIDTH.to_string());
+    variables.insert("screen_height".to_string(), VIRTUAL_HEIGHT.to_string());
+    variables.insert("trigger_corner".to_string(), trigger_name.to_string());
+    variables.insert("segmentation".to_string(), segmentation_description.to_string());
+    variables.insert(
+        "document_name".to_string(),
+        document.as_ref().map(|document| document.name.clone()).unwrap_or_else(|| "unknown".to_string()),
+    );
+    variables.insert(
+        "page_number".to_string(),
+        document
+            .and_then(|document| document.page)
+            .map(|page| page.to_string())
+            .unwrap_or_else(|| "unknown".to_string()),
+    );
+    variables.insert("history".to_string(), history.describe());
+    variables.insert("ink".to_string(), describe_ink(strokes, ink_format));
+    variables.insert("ink_bounds".to_string(), describe_ink_bounds(strokes));
+    variables
+}
+
+/// What the main loop remembers between triggers
+struct TriggerState {
+    pen_symbols: PenSymbolRecognizer,
+    // Strokes the user drew since the last interaction
+    ink: InkLog,
+    // The prompt to use when neither a trigger zone nor the router picks one; gestures can switch it
+    active_prompt: String,
+    last_pen_activity: Instant,
+    last_idle_trigger: Option<Instant>,
+}
+
+impl TriggerState {
+    fn new(config: &Config) -> Self {
+        Self {
+            pen_symbols: PenSymbolRecognizer::new(),
+            ink: InkLog::new(),
+            active_prompt: config.prompt.clone(),
+            last_pen_activity: Instant::now(),
+            last_idle_trigger: None,
+        }
+    }
+
+    /// The idle trigger fires once the pen has been quiet long enough after drawing something new,
+    /// and not more often than `idle_min_interval_secs`
+    fn idle_trigger_due(&self, config: &Config) -> bool {
+        let Some(idle_secs) = config.idle_trigger_secs else {
+            return false;
+        };
+        let rested = self
+            .last_idle_trigger
+            .is_none_or(|last| last.elapsed() >= Duration::from_secs(config.idle_min_interval_secs));
+        !self.ink.is_empty() && rested && self.last_pen_activity.elapsed() >= Duration::from_secs(idle_secs)
+    }
+}
+
+/// Wait for a touch, pen or idle trigger, carrying out any other gesture actions along the way
+fn wait_for_trigger(config: &Config, shared: &SharedState, pen_input: &PenInput, state: &mut TriggerState) -> Result<TriggerZone> {
+    let mut touch = lock!(shared.touch);
+    if !touch.is_listening() && !pen_input.is_listening() {
+        return Err(anyhow::anyhow!("No touch device or pen trigger to wait on; use --no-trigger"));
+    }
+
+    let mut waiting_for: Vec<String> = config.trigger_zones().iter().map(|zone| format!("hand-touch in {}", zone.name())).collect();
+    waiting_for.extend(
+        config
+            .pen_triggers
+            .iter()
+            .map(|trigger| format!("pen {} in {}", trigger.symbol.name(), trigger.zone.name())),
+    );
+    if let Some(idle_secs) = config.idle_trigger_secs {
+        waiting_for.push(format!("{}s of pen idle after new ink", idle_secs));
+    }
+    info!("Waiting for trigger ({})...", waiting_for.join(", "));
+
+    touch.discard_pending();
+    loop {
+        match touch.poll_trigger(INPUT_POLL_INTERVAL)? {
+            Some(TouchAction::Trigger(zone)) => return Ok(zone),
+            Some(TouchAction::Undo) => {
+                info!("Undoing the last edit");
+                lock!(shared.keyboard).key_cmd_undo()?;
+            }
+            Some(TouchAction::SwitchPrompt(prompt)) => {
+                info!("Switching the default prompt to {}", prompt);
+                state.active_prompt = prompt;
+            }
+            Some(TouchAction::Cancel) => debug!("Nothing to cancel while waiting for a trigger"),
+            None => {}
+        }
+
+        if pen_input.is_listening() {
+            for stroke in pen_input.poll_strokes(INPUT_POLL_INTERVAL)? {
+                state.last_pen_activity = Instant::now();
+                if let Some(trigger) = state.pen_symbols.matching_trigger(&stroke, &config.pen_triggers) {
+                    // The trigger symbol isn't part of the page; an x's first stroke is already in the log
+                    if trigger.symbol == PenSymbol::X {
+                        state.ink.pop();
+                    }
+                    let zone = trigger.fired_zone();
+                    info!("Pen trigger {}", zone.name());
+                    return Ok(zone);
+                }
+                state.ink.push(stroke);
+            }
+        }
+
+        if state.idle_trigger_due(config) {
+            info!("Pen has been idle after new ink, triggering automatically");
+            state.last_idle_trigger = Some(Instant::now());
+            return Ok(TriggerZone {
+                name: Some("idle".to_string()),
+                prompt: config.idle_prompt.clone(),
+                ..Default::default()
+            });
+        }
+    }
+}
+
+fn ghostwriter(args: &Args) -> Result<()> {
+    let config = Config::load(args)?;
+
+    // Handle --save-config option
+    if args.save_config {
+        config.save()?;
+        println!("Configuration saved to {:?}", Config::config_path()?);
+        return Ok(());
+    }
+
+    let trigger_zones = config.trigger_zones();
+    check_trigger_zones(&trigger_zones);
+    let keyboard = shared!(Keyboard::new(config.no_draw || config.no_keyboard, config.no_draw_progress,));
+    let pen = shared!(Pen::new(config.no_draw, config.pen_device.as_deref())?);
+    let touch = shared!(Touch::new(
+        config.no_draw,
+        config.touch_device.as_deref(),
+        trigger_zones.clone(),
+        config.gestures.clone()
+    )?);
+    if let Some(replay_touch_log) = &config.replay_touch_log {
+        lock!(touch).replay_log(replay_touch_log)?;
+    }
+    if let Some(record_trace) = &config.record_trace {
+        let recorder = TraceRecorder::create(record_trace)?;
+        lock!(pen).set_recorder(recorder.clone());
+        lock!(touch).set_recorder(recorder);
+    }
+    let cancel = CancelToken::new();
+    lock!(keyboard).set_cancel_token(cancel.clone());
+    lock!(pen).set_cancel_token(cancel.clone());
+    lock!(pen).set_speed(DrawSpeed::from_string(&config.draw_speed)?);
+    lock!(pen).set_progress_callback(log_draw_progress());
+    let ink_format = config.ink_context.as_deref().map(InkFormat::from_string).transpose()?;
+    let palm_rejection = !config.no_palm_rejection && !config.no_trigger;
+    let needs_pen_input = !config.pen_triggers.is_empty() || ink_format.is_some() || config.idle_trigger_secs.is_some() || palm_rejection;
+    let pen_input = {
+        let pen = lock!(pen);
+        PenInput::new(pen.device_path().filter(|_| needs_pen_input), pen.drawing_activity())?
+    };
+    if palm_rejection {
+        let max_touch_major = (config.palm_touch_major > 0).then_some(config.palm_touch_major);
+        lock!(touch).reject_palms(max_touch_major, pen_input.is_listening().then(|| pen_input.proximity()));
+    }
+    lock!(touch).listen(cancel.clone())?;
+    let mut state = TriggerState::new(&config);
+    let shared = SharedState {
+        keyboard: Arc::clone(&keyboard),
+        pen: Arc::clone(&pen),
+        touch: Arc::clone(&touch),
+        history: shared!(InteractionHistory::new(MAX_HISTORY_ENTRIES)),
+    };
+
+    // Give time for the virtual keyboard to be plugged in
+    sleep(Duration::from_millis(1000));
+
+    lock!(touch).tap_middle_bottom()?;
+    sleep(Duration::from_millis(1000));
+
+    lock!(keyboard).progress("Keyboard loaded...")?;
+
+    // Make sure the prompts, their examples and engine settings are usable before we wait for a trigger
+    let engine_name = check_prompt(&config, &config.prompt)?;
+    info!("Default prompt {} will use the {} engine", config.prompt, engine_name);
+    for zone in &trigger_zones {
+        if let Some(prompt) = &zone.prompt {
+            let engine_name = check_prompt(&config, prompt)?;
+            info!("Trigger zone {} uses prompt {} on the {} engine", zone.name(), prompt, engine_name);
+        }
+    }
+    if let Some(idle_prompt) = &config.idle_prompt {
+        let engine_name = check_prompt(&config, idle_prompt)?;
+        info!("Idle trigger uses prompt {} on the {} engine", idle_prompt, engine_name);
+    }
+    for binding in &config.gestures {
+        if let Some(prompt) = &binding.prompt {
+            let engine_name = check_prompt(&config, prompt)?;
+            info!("Gesture {} uses prompt {} on the {} engine", binding.gesture.name(), prompt, engine_name);
+        }
+    }
+    if config.router {
+        for route in &config.router_routes {
+            let engine_name = check_prompt(&config, &route.prompt)?;
+            info!("Route {} uses prompt {} on the {} engine", route.name, route.prompt, engine_name);
+        }
     }
 
     lock!(keyboard).progress("Tools initialized.")?;
@@ -338,20 +1034,20 @@ fn ghostwriter(args: &Args) -> Result<()> {
     sleep(Duration::from_millis(1000));
 
     loop {
-        if config.no_trigger {
+        let trigger_zone = if config.no_trigger {
             debug!("Skipping waiting for trigger");
+            None
         } else {
-            info!(
-                "Waiting for trigger (hand-touch in the {} corner)...",
-                match TriggerCorner::from_string(&config.trigger_corner).unwrap() {
-                    TriggerCorner::UpperRight => "upper-right",
-                    TriggerCorner::UpperLeft => "upper-left",
-                    TriggerCorner::LowerRight => "lower-right",
-                    TriggerCorner::LowerLeft => "lower-left",
-                }
-            );
-            lock!(touch).wait_for_trigger()?;
-        }
+            Some(wait_for_trigger(&config, &shared, &pen_input, &mut state)?)
+        };
+        let trigger_name = trigger_zone.as_ref().map(|zone| zone.name()).unwrap_or_else(|| "none".to_string());
+        // An abort gesture made before this trigger shouldn't cancel it
+        cancel.reset();
+
+        // Everything drawn since the last interaction
+        state.ink.extend(pen_input.take_pending());
+        let strokes = state.ink.take();
+        debug!("{} new pen stroke(s), bounds {}", strokes.len(), describe_ink_bounds(&strokes));
 
         // Sleep a bit to differentiate the touches
         sleep(Duration::from_millis(100));
@@ -378,11 +1074,23 @@ fn ghostwriter(args: &Args) -> Result<()> {
             return Ok(());
         }
 
-        let prompt_general_raw = load_config(&config.prompt);
-        let prompt_general_json = serde_json::from_str::<serde_json::Value>(prompt_general_raw.as_str())?;
-        let prompt = prompt_general_json["prompt"]
-            .as_str()
-            .ok_or_else(|| anyhow::anyhow!("Prompt file '{}' missing required 'prompt' field", config.prompt))?;
+        let prompt_filename = if let Some(prompt) = trigger_zone.as_ref().and_then(|zone| zone.prompt.clone()) {
+            info!("Trigger zone {} uses prompt {}", trigger_name, prompt);
+            prompt
+        } else if config.router {
+            info!("Routing the page to a prompt");
+            lock!(keyboard).progress("routing...")?;
+            match route_prompt(&config, &base64_image, &cancel) {
+                Ok(prompt_filename) => prompt_filename,
+                Err(e) => {
+                    log::warn!("Routing failed, falling back to {}: {}", state.active_prompt, e);
+                    state.active_prompt.clone()
+                }
+            }
+        } else {
+            state.active_prompt.clone()
+        };
+        let prompt = Prompt::load(&prompt_filename)?;
 
         let segmentation_description = if config.apply_segmentation {
             info!("Building image segmentation");
@@ -401,21 +1109,52 @@ fn ghostwriter(args: &Args) -> Result<()> {
         };
         debug!("Segmentation description: {}", segmentation_description);
 
-        engine.clear_content();
+        let variables = template_variables(
+            &trigger_name,
+            &segmentation_description,
+            &lock!(shared.history),
+            &strokes,
+            ink_format.unwrap_or(InkFormat::Svg),
+        );
+        let (engine_name, engine_options) = engine_options(&config, &prompt)?;
+        let mut engine = create_engine(&engine_name, &engine_options, &cancel)?;
+        register_tools(engine.as_mut(), &prompt.tool_names(), &config, &shared, &variables)?;
+        for example in prompt.usable_examples() {
+            engine.add_example(example);
+        }
+
         engine.add_image_content(&base64_image);
 
-        if config.apply_segmentation {
+        // Prompts that place the segmentation themselves don't need it repeated
+        if config.apply_segmentation && !prompt.prompt.contains("{{segmentation}}") {
             engine.add_text_content(
                format!("Here are interesting regions based on an automatic segmentation algorithm. Use them to help identify the exact location of interesting features.\n\n{}", segmentation_description).as_str()
             );
         }
 
-        engine.add_text_content(prompt);
+        // Likewise for the user's strokes
+        if ink_format.is_some() && !strokes.is_empty() && !prompt.prompt.contains("{{ink") {
+            engine.add_text_content(&format!(
+                "Here are the pen strokes the user drew since the last interaction, oldest first, in the same coordinates as the screenshot. The new ink is in the box {}.\n\n{}",
+                variables["ink_bounds"], variables["ink"]
+            ));
+        }
+
+        engine.add_text_content(&render_template(&prompt.prompt, &variables));
 
         info!("Executing the engine (call out to {}", engine_name);
         lock!(keyboard).progress("thinking...")?;
-        if engine.execute().is_err() {
-            lock!(keyboard).progress(" model error. ")?;
+        match engine.execute() {
+            Err(e) if is_cancelled(&e) => {}
+            Err(e) => {
+                log::error!("Model call to {} failed: {}", engine_name, e);
+                lock!(keyboard).progress(" model error. ")?
+            }
+            Ok(()) => {}
+        }
+        if cancel.is_cancelled() {
+            info!("Interaction cancelled, back to waiting");
+            lock!(keyboard).progress_end()?;
         }
 
         if config.no_loop {
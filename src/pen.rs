@@ -2,41 +2,276 @@ use anyhow::Result;
 use evdev::EventType as EvdevEventType;
 use evdev::{Device, InputEvent};
 use log::info;
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::device::DeviceModel;
+use crate::cancel::CancelToken;
+use crate::device::{find_input_device, DeviceModel, InputDeviceKind};
+use crate::stroke_planner::{bitmap_runs, estimate_draw_time, plan_strokes};
+use crate::svg_paths::Polyline;
+use crate::trace::{TraceDevice, TraceRecorder};
 
 // Output dimensions remain the same for both devices
 const VIRTUAL_WIDTH: u32 = 768;
 const VIRTUAL_HEIGHT: u32 = 1024;
+// ABS_PRESSURE for a full-pressure stroke
+const FULL_PRESSURE: i32 = 2630;
+// ABS_TILT_X/Y units per degree of lean
+const TILT_UNITS_PER_DEGREE: f32 = 100.0;
+// Gap between the sweeps erase_rect makes across a region, in virtual pixels
+const ERASER_SWEEP_SPACING: i32 = 4;
+// How many moves a long stroke makes between checks for a cancel
+const CANCEL_CHECK_MOVES: usize = 200;
+// Pen events stamped this soon after a drawing ends are still the tail end of it
+const DRAWING_GRACE_SECS: f64 = 0.25;
+// Input events (SYN_REPORT included) sent to put the pen down, lift it and move it
+pub const PEN_DOWN_EVENTS: usize = 7;
+pub const PEN_UP_EVENTS: usize = 6;
+pub const MOVE_EVENTS: usize = 3;
+// A stroke lifts the pen (in case it's down), moves to the start, lowers, then lifts again at
+// the end; each step along it is one more move
+pub const STROKE_EVENTS: usize = PEN_UP_EVENTS + MOVE_EVENTS + PEN_DOWN_EVENTS + PEN_UP_EVENTS;
+
+/// How fast the pen moves, from `--draw-speed`. xochitl on slower firmware drops events that
+/// come in too quickly, which shows up as gaps and missing strokes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrawSpeed {
+    /// No pauses and two pixels per move
+    Fast,
+    /// A short pause whenever the pen goes down or up
+    Normal,
+    /// Longer pauses, and a little rest after every move too
+    Careful,
+}
+
+impl DrawSpeed {
+    pub fn from_string(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "fast" => Ok(DrawSpeed::Fast),
+            "normal" => Ok(DrawSpeed::Normal),
+            "careful" => Ok(DrawSpeed::Careful),
+            _ => Err(anyhow::anyhow!("Invalid draw speed: {}. Use fast, normal or careful", s)),
+        }
+    }
+
+    /// Longest move, in virtual pixels, along a stroke
+    pub fn step(self) -> f32 {
+        match self {
+            DrawSpeed::Fast => 2.0,
+            DrawSpeed::Normal | DrawSpeed::Careful => 1.0,
+        }
+    }
+
+    /// Longest move, in input units, for `draw_line`
+    fn line_step(self) -> f32 {
+        match self {
+            DrawSpeed::Fast => 10.0,
+            DrawSpeed::Normal => 5.0,
+            DrawSpeed::Careful => 3.0,
+        }
+    }
+
+    /// Pause after the pen goes down or up
+    pub fn transition_pause(self) -> Duration {
+        match self {
+            DrawSpeed::Fast => Duration::ZERO,
+            DrawSpeed::Normal => Duration::from_millis(1),
+            DrawSpeed::Careful => Duration::from_millis(5),
+        }
+    }
+
+    /// Pause after every move
+    pub fn move_pause(self) -> Duration {
+        match self {
+            DrawSpeed::Fast | DrawSpeed::Normal => Duration::ZERO,
+            DrawSpeed::Careful => Duration::from_micros(200),
+        }
+    }
+}
+
+/// How far a drawing has got, passed to the progress callback after every stroke
+#[derive(Debug, Clone, Copy)]
+pub struct DrawProgress {
+    pub strokes_done: usize,
+    pub strokes_total: usize,
+    pub elapsed: Duration,
+    pub estimated: Duration,
+}
+
+pub type ProgressCallback = Box<dyn FnMut(DrawProgress) + Send>;
+
+/// How hard the pen presses, and how it leans, while drawing a stroke. Pressure-sensitive
+/// brushes in xochitl draw lighter pressure as thinner or fainter lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PenStyle {
+    /// From 0 (barely touching) to 1 (full pressure)
+    pub pressure: f32,
+    /// Lean in degrees along x and y, if any
+    pub tilt: Option<(f32, f32)>,
+    /// Rub out with the eraser end (BTN_TOOL_RUBBER) instead of drawing
+    pub eraser: bool,
+}
+
+impl Default for PenStyle {
+    fn default() -> Self {
+        Self {
+            pressure: 1.0,
+            tilt: None,
+            eraser: false,
+        }
+    }
+}
+
+/// A polyline in virtual coordinates, drawn as one continuous stroke in the given style
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    pub points: Polyline,
+    pub style: PenStyle,
+}
+
+impl From<Polyline> for Stroke {
+    fn from(points: Polyline) -> Self {
+        Self {
+            points,
+            style: PenStyle::default(),
+        }
+    }
+}
 
 pub struct Pen {
     device: Option<Device>,
+    device_path: Option<String>,
     device_model: DeviceModel,
+    cancel: CancelToken,
+    speed: DrawSpeed,
+    progress: Option<ProgressCallback>,
+    recorder: Option<TraceRecorder>,
+    drawing: DrawingActivity,
+}
+
+/// When ghostwriter is drawing, shared with the pen reader so it can tell our strokes from the
+/// user's. Times are seconds since the epoch, like the kernel's event timestamps.
+#[derive(Clone)]
+pub struct DrawingActivity(Arc<Mutex<DrawingWindow>>);
+
+struct DrawingWindow {
+    // Drawing calls in progress; they nest, such as erase_rect's sweeps
+    depth: u32,
+    started: f64,
+    finished: f64,
+}
+
+fn epoch_secs() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+impl DrawingActivity {
+    fn new() -> Self {
+        Self(Arc::new(Mutex::new(DrawingWindow {
+            depth: 0,
+            started: f64::INFINITY,
+            finished: f64::NEG_INFINITY,
+        })))
+    }
+
+    fn start(&self) -> DrawingGuard {
+        let mut window = self.0.lock().unwrap();
+        if window.depth == 0 {
+            window.started = epoch_secs();
+        }
+        window.depth += 1;
+        DrawingGuard(self.clone())
+    }
+
+    /// Whether a pen event stamped `time` came from our own drawing. The reader can fall behind,
+    /// so this goes by when the event happened rather than whether we're drawing right now.
+    pub fn is_ours(&self, time: f64) -> bool {
+        let window = self.0.lock().unwrap();
+        time >= window.started && (window.depth > 0 || time <= window.finished + DRAWING_GRACE_SECS)
+    }
+}
+
+/// Marks the end of a drawing call, however it returns
+struct DrawingGuard(DrawingActivity);
+
+impl Drop for DrawingGuard {
+    fn drop(&mut self) {
+        let mut window = (self.0).0.lock().unwrap();
+        window.depth -= 1;
+        if window.depth == 0 {
+            window.finished = epoch_secs();
+        }
+    }
 }
 
 impl Pen {
-    pub fn new(no_draw: bool) -> Self {
+    /// Open the pen device, or the one at `pen_device` if given, unless `no_draw`
+    pub fn new(no_draw: bool, pen_device: Option<&str>) -> Result<Self> {
         let device_model = DeviceModel::detect();
         info!("Pen using device model: {}", device_model.name());
 
-        let pen_input_device = match device_model {
-            DeviceModel::Remarkable2 => "/dev/input/event1",
-            DeviceModel::RemarkablePaperPro => "/dev/input/event2",
-            DeviceModel::Unknown => "/dev/input/event1", // Default to RM2
+        let (device, device_path) = if no_draw {
+            (None, None)
+        } else {
+            let path = find_input_device(InputDeviceKind::Pen, pen_device)?;
+            let device = Device::open(&path).map_err(|e| anyhow::anyhow!("Failed to open pen device {}: {}", path, e))?;
+            (Some(device), Some(path))
         };
 
-        let device = if no_draw { None } else { Some(Device::open(pen_input_device).unwrap()) };
+        Ok(Self {
+            device,
+            device_path,
+            device_model,
+            cancel: CancelToken::new(),
+            speed: DrawSpeed::Normal,
+            progress: None,
+            recorder: None,
+            drawing: DrawingActivity::new(),
+        })
+    }
+
+    /// Where the pen device is, if it was opened
+    pub fn device_path(&self) -> Option<&str> {
+        self.device_path.as_deref()
+    }
+
+    /// When ghostwriter itself is drawing
+    pub fn drawing_activity(&self) -> DrawingActivity {
+        self.drawing.clone()
+    }
+
+    fn start_drawing(&self) -> DrawingGuard {
+        self.drawing.start()
+    }
+
+    /// Drawing stops, with the pen lifted, soon after this token is cancelled: at the next
+    /// stroke, or part way along a long one
+    pub fn set_cancel_token(&mut self, cancel: CancelToken) {
+        self.cancel = cancel;
+    }
+
+    pub fn set_speed(&mut self, speed: DrawSpeed) {
+        self.speed = speed;
+    }
+
+    /// Record every event the pen sends, even with no device open
+    pub fn set_recorder(&mut self, recorder: TraceRecorder) {
+        self.recorder = Some(recorder);
+    }
 
-        Self { device, device_model }
+    /// Called after each stroke of a planned drawing (`draw_strokes`, `draw_bitmap`)
+    pub fn set_progress_callback(&mut self, callback: ProgressCallback) {
+        self.progress = Some(callback);
     }
 
     pub fn draw_line_screen(&mut self, p1: (i32, i32), p2: (i32, i32)) -> Result<()> {
-        self.draw_line(self.virtual_to_input(p1), self.virtual_to_input(p2))
+        let to_input = |(x, y): (i32, i32)| self.virtual_to_input((x as f32, y as f32));
+        self.draw_line(to_input(p1), to_input(p2))
     }
 
     pub fn draw_line(&mut self, (x1, y1): (i32, i32), (x2, y2): (i32, i32)) -> Result<()> {
+        let _drawing = self.start_drawing();
         // trace!("Drawing from ({}, {}) to ({}, {})", x1, y1, x2, y2);
 
         // We know this is a straight line
@@ -46,9 +281,8 @@ impl Pen {
         // And then for each step add the right amount to x and y
 
         let length = ((x2 as f32 - x1 as f32).powf(2.0) + (y2 as f32 - y1 as f32).powf(2.0)).sqrt();
-        // 5.0 is the maximum distance between points
-        // If this is too small
-        let steps = (length / 5.0).ceil() as i32;
+        // The speed profile sets the maximum distance between points
+        let steps = ((length / self.speed.line_step()).ceil() as i32).max(1);
         let dx = (x2 - x1) / steps;
         let dy = (y2 - y1) / steps;
         // trace!(
@@ -57,6 +291,7 @@ impl Pen {
         // );
 
         self.pen_up()?;
+        self.cancel.check()?;
         self.goto_xy((x1, y1))?;
         self.pen_down()?;
 
@@ -72,29 +307,108 @@ impl Pen {
         Ok(())
     }
 
-    pub fn draw_bitmap(&mut self, bitmap: &[Vec<bool>]) -> Result<()> {
-        let mut is_pen_down = false;
-        for (y, row) in bitmap.iter().enumerate() {
-            for (x, &pixel) in row.iter().enumerate() {
-                if pixel {
-                    if !is_pen_down {
-                        self.goto_xy_virtual((x as i32, y as i32))?;
-                        self.pen_down()?;
-                        is_pen_down = true;
-                        sleep(Duration::from_millis(1));
-                    }
-                    self.goto_xy_virtual((x as i32, y as i32))?;
-                    self.goto_xy_virtual((x as i32 + 1, y as i32))?;
-                } else if is_pen_down {
+    /// Draw a polyline in virtual coordinates as one continuous stroke
+    pub fn draw_polyline(&mut self, points: &[(f32, f32)]) -> Result<()> {
+        let _drawing = self.start_drawing();
+        self.trace_polyline(points, PenStyle::default(), true)
+    }
+
+    /// Rub out along a polyline in virtual coordinates with the eraser end
+    pub fn erase_stroke(&mut self, points: &[(f32, f32)]) -> Result<()> {
+        let _drawing = self.start_drawing();
+        let style = PenStyle {
+            eraser: true,
+            ..PenStyle::default()
+        };
+        self.trace_polyline(points, style, true)
+    }
+
+    /// Rub out everything in a virtual (x, y, width, height) box: back and forth across it, then
+    /// once around the edge
+    pub fn erase_rect(&mut self, (x, y, width, height): (i32, i32, i32, i32)) -> Result<()> {
+        let _drawing = self.start_drawing();
+        let (left, top) = (x.max(0), y.max(0));
+        let (right, bottom) = ((x + width).min(VIRTUAL_WIDTH as i32 - 1), (y + height).min(VIRTUAL_HEIGHT as i32 - 1));
+        if right < left || bottom < top {
+            return Ok(());
+        }
+        let (left, top, right, bottom) = (left as f32, top as f32, right as f32, bottom as f32);
+
+        let mut sweep = Vec::new();
+        let mut row = top;
+        let mut rightward = true;
+        while row <= bottom {
+            let (from, to) = if rightward { (left, right) } else { (right, left) };
+            sweep.push((from, row));
+            sweep.push((to, row));
+            row += ERASER_SWEEP_SPACING as f32;
+            rightward = !rightward;
+        }
+        self.erase_stroke(&sweep)?;
+        self.erase_stroke(&[(left, top), (right, top), (right, bottom), (left, bottom), (left, top)])
+    }
+
+    /// Draw strokes in virtual coordinates, reordered to keep the pen's travel short
+    pub fn draw_strokes(&mut self, strokes: Vec<Stroke>) -> Result<()> {
+        self.draw_planned(strokes, true, 1)
+    }
+
+    /// Draw a bitmap with `scale` pixels per virtual pixel as one straight stroke per horizontal
+    /// run of inked pixels, see `bitmap_runs`
+    pub fn draw_bitmap(&mut self, bitmap: &[Vec<bool>], skip_scanlines: bool, scale: u32) -> Result<()> {
+        self.draw_planned(bitmap_runs(bitmap, skip_scanlines, scale).into_iter().map(Stroke::from).collect(), false, scale)
+    }
+
+    fn draw_planned(&mut self, strokes: Vec<Stroke>, interpolated: bool, scale: u32) -> Result<()> {
+        let _drawing = self.start_drawing();
+        let strokes = plan_strokes(strokes, scale);
+        let estimated = estimate_draw_time(&strokes, interpolated, self.speed);
+        info!("Drawing {} strokes, estimated {:.1}s", strokes.len(), estimated.as_secs_f32());
+        let started = Instant::now();
+        for (index, stroke) in strokes.iter().enumerate() {
+            self.trace_polyline(&stroke.points, stroke.style, interpolated)?;
+            if let Some(progress) = &mut self.progress {
+                progress(DrawProgress {
+                    strokes_done: index + 1,
+                    strokes_total: strokes.len(),
+                    elapsed: started.elapsed(),
+                    estimated,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Move along a polyline with the pen down, in steps no longer than the speed profile allows
+    /// when `interpolated` so long segments don't jump, or straight to each point otherwise
+    fn trace_polyline(&mut self, points: &[(f32, f32)], style: PenStyle, interpolated: bool) -> Result<()> {
+        let Some(&(x, y)) = points.first() else {
+            return Ok(());
+        };
+
+        self.pen_up()?;
+        self.cancel.check()?;
+        self.goto_xy_virtual((x, y))?;
+        self.pen_down_with(style)?;
+        let mut moves = 0;
+        for pair in points.windows(2) {
+            let ((x1, y1), (x2, y2)) = (pair[0], pair[1]);
+            let steps = if interpolated {
+                ((x2 - x1).abs().max((y2 - y1).abs()) / self.speed.step()).ceil().max(1.0) as i32
+            } else {
+                1
+            };
+            for i in 1..=steps {
+                let t = i as f32 / steps as f32;
+                self.goto_xy_virtual((x1 + (x2 - x1) * t, y1 + (y2 - y1) * t))?;
+                moves += 1;
+                if moves % CANCEL_CHECK_MOVES == 0 && self.cancel.is_cancelled() {
                     self.pen_up()?;
-                    is_pen_down = false;
-                    sleep(Duration::from_millis(1));
+                    return self.cancel.check();
                 }
             }
-            self.pen_up()?;
-            is_pen_down = false;
-            sleep(Duration::from_millis(5));
         }
+        self.pen_up()?;
         Ok(())
     }
 
@@ -117,78 +431,130 @@ impl Pen {
     // }
 
     pub fn pen_down(&mut self) -> Result<()> {
-        if let Some(device) = &mut self.device {
-            device.send_events(&[
-                InputEvent::new(EvdevEventType::KEY.0, 320, 1),           // BTN_TOOL_PEN
-                InputEvent::new(EvdevEventType::KEY.0, 330, 1),           // BTN_TOUCH
-                InputEvent::new(EvdevEventType::ABSOLUTE.0, 24, 2630),    // ABS_PRESSURE (max pressure)
-                InputEvent::new(EvdevEventType::ABSOLUTE.0, 25, 0),       // ABS_DISTANCE
-                InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0), // SYN_REPORT
-            ])?;
-        }
-        Ok(())
+        self.pen_down_with(PenStyle::default())
+    }
+
+    /// Put the pen down pressing and leaning as `style` says
+    pub fn pen_down_with(&mut self, style: PenStyle) -> Result<()> {
+        let pressure = (style.pressure.clamp(0.0, 1.0) * FULL_PRESSURE as f32).round().max(1.0) as i32;
+        let (tilt_x, tilt_y) = style.tilt.unwrap_or((0.0, 0.0));
+        let tilt = |degrees: f32| (degrees.clamp(-90.0, 90.0) * TILT_UNITS_PER_DEGREE).round() as i32;
+        let tool = if style.eraser { 321 } else { 320 };
+        let events: [InputEvent; PEN_DOWN_EVENTS] = [
+            InputEvent::new(EvdevEventType::KEY.0, tool, 1),               // BTN_TOOL_PEN or BTN_TOOL_RUBBER
+            InputEvent::new(EvdevEventType::KEY.0, 330, 1),                // BTN_TOUCH
+            InputEvent::new(EvdevEventType::ABSOLUTE.0, 24, pressure),     // ABS_PRESSURE
+            InputEvent::new(EvdevEventType::ABSOLUTE.0, 25, 0),            // ABS_DISTANCE
+            InputEvent::new(EvdevEventType::ABSOLUTE.0, 26, tilt(tilt_x)), // ABS_TILT_X
+            InputEvent::new(EvdevEventType::ABSOLUTE.0, 27, tilt(tilt_y)), // ABS_TILT_Y
+            InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0),      // SYN_REPORT
+        ];
+        self.send(&events, self.speed.transition_pause())
     }
 
     pub fn pen_up(&mut self) -> Result<()> {
-        if let Some(device) = &mut self.device {
-            device.send_events(&[
-                InputEvent::new(EvdevEventType::ABSOLUTE.0, 24, 0),       // ABS_PRESSURE
-                InputEvent::new(EvdevEventType::ABSOLUTE.0, 25, 100),     // ABS_DISTANCE
-                InputEvent::new(EvdevEventType::KEY.0, 330, 0),           // BTN_TOUCH
-                InputEvent::new(EvdevEventType::KEY.0, 320, 0),           // BTN_TOOL_PEN
-                InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0), // SYN_REPORT
-            ])?;
-        }
-        Ok(())
+        let events: [InputEvent; PEN_UP_EVENTS] = [
+            InputEvent::new(EvdevEventType::ABSOLUTE.0, 24, 0),       // ABS_PRESSURE
+            InputEvent::new(EvdevEventType::ABSOLUTE.0, 25, 100),     // ABS_DISTANCE
+            InputEvent::new(EvdevEventType::KEY.0, 330, 0),           // BTN_TOUCH
+            InputEvent::new(EvdevEventType::KEY.0, 320, 0),           // BTN_TOOL_PEN
+            InputEvent::new(EvdevEventType::KEY.0, 321, 0),           // BTN_TOOL_RUBBER
+            InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0), // SYN_REPORT
+        ];
+        self.send(&events, self.speed.transition_pause())
     }
 
-    pub fn goto_xy_virtual(&mut self, point: (i32, i32)) -> Result<()> {
+    /// Move to a point in virtual coordinates; fractions of a virtual pixel carry through to the
+    /// digitizer's much finer grid
+    pub fn goto_xy_virtual(&mut self, point: (f32, f32)) -> Result<()> {
         self.goto_xy(self.virtual_to_input(point))
     }
 
     pub fn goto_xy(&mut self, (x, y): (i32, i32)) -> Result<()> {
+        let events: [InputEvent; MOVE_EVENTS] = [
+            InputEvent::new(EvdevEventType::ABSOLUTE.0, 0, x),        // ABS_X
+            InputEvent::new(EvdevEventType::ABSOLUTE.0, 1, y),        // ABS_Y
+            InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0), // SYN_REPORT
+        ];
+        self.send(&events, self.speed.move_pause())
+    }
+
+    /// Write events to the pen device, then pause for `pause_after`, and add them to the trace if
+    /// one is being recorded
+    fn send(&mut self, events: &[InputEvent], pause_after: Duration) -> Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(TraceDevice::Pen, events)?;
+        }
         if let Some(device) = &mut self.device {
-            device.send_events(&[
-                InputEvent::new(EvdevEventType::ABSOLUTE.0, 0, x),        // ABS_X
-                InputEvent::new(EvdevEventType::ABSOLUTE.0, 1, y),        // ABS_Y
-                InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0), // SYN_REPORT
-            ])?;
+            device.send_events(events)?;
+            pause(pause_after);
         }
         Ok(())
     }
 
     pub fn max_x_value(&self) -> i32 {
-        match self.device_model {
-            DeviceModel::Remarkable2 => 15725,
-            DeviceModel::RemarkablePaperPro => 11180,
-            DeviceModel::Unknown => 15725, // Default to RM2
-        }
+        max_x_value(self.device_model)
     }
 
     pub fn max_y_value(&self) -> i32 {
-        match self.device_model {
-            DeviceModel::Remarkable2 => 20966,
-            DeviceModel::RemarkablePaperPro => 15340,
-            DeviceModel::Unknown => 20966, // Default to RM2
-        }
+        max_y_value(self.device_model)
     }
 
-    fn virtual_to_input(&self, (x, y): (i32, i32)) -> (i32, i32) {
+    fn virtual_to_input(&self, (x, y): (f32, f32)) -> (i32, i32) {
         // Swap and normalize the coordinates
-        let x_normalized = x as f32 / VIRTUAL_WIDTH as f32;
-        let y_normalized = y as f32 / VIRTUAL_HEIGHT as f32;
+        let x_normalized = x / VIRTUAL_WIDTH as f32;
+        let y_normalized = y / VIRTUAL_HEIGHT as f32;
 
         match self.device_model {
             DeviceModel::RemarkablePaperPro => {
-                let x_input = (x_normalized * self.max_x_value() as f32) as i32;
-                let y_input = (y_normalized * self.max_y_value() as f32) as i32;
+                let x_input = (x_normalized * self.max_x_value() as f32).round() as i32;
+                let y_input = (y_normalized * self.max_y_value() as f32).round() as i32;
                 (x_input, y_input)
             }
             _ => {
-                let x_input = ((1.0 - y_normalized) * self.max_y_value() as f32) as i32;
-                let y_input = (x_normalized * self.max_x_value() as f32) as i32;
+                let x_input = ((1.0 - y_normalized) * self.max_y_value() as f32).round() as i32;
+                let y_input = (x_normalized * self.max_x_value() as f32).round() as i32;
                 (x_input, y_input)
             }
         }
     }
 }
+
+fn max_x_value(device_model: DeviceModel) -> i32 {
+    match device_model {
+        DeviceModel::Remarkable2 => 15725,
+        DeviceModel::RemarkablePaperPro => 11180,
+        DeviceModel::Unknown => 15725, // Default to RM2
+    }
+}
+
+fn max_y_value(device_model: DeviceModel) -> i32 {
+    match device_model {
+        DeviceModel::Remarkable2 => 20966,
+        DeviceModel::RemarkablePaperPro => 15340,
+        DeviceModel::Unknown => 20966, // Default to RM2
+    }
+}
+
+/// Map a pen digitizer position to virtual coordinates, the reverse of `Pen::virtual_to_input`
+pub fn input_to_virtual(device_model: DeviceModel, (x, y): (i32, i32)) -> (i32, i32) {
+    match device_model {
+        DeviceModel::RemarkablePaperPro => {
+            let x_normalized = x as f32 / max_x_value(device_model) as f32;
+            let y_normalized = y as f32 / max_y_value(device_model) as f32;
+            ((x_normalized * VIRTUAL_WIDTH as f32) as i32, (y_normalized * VIRTUAL_HEIGHT as f32) as i32)
+        }
+        _ => {
+            // The RM2 digitizer is rotated: its x runs up the screen and its y runs across
+            let x_normalized = y as f32 / max_x_value(device_model) as f32;
+            let y_normalized = 1.0 - x as f32 / max_y_value(device_model) as f32;
+            ((x_normalized * VIRTUAL_WIDTH as f32) as i32, (y_normalized * VIRTUAL_HEIGHT as f32) as i32)
+        }
+    }
+}
+
+fn pause(duration: Duration) {
+    if !duration.is_zero() {
+        sleep(duration);
+    }
+}
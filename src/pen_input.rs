@@ -0,0 +1,214 @@
+use anyhow::Result;
+use evdev::Device;
+use log::{error, info, trace};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::device::DeviceModel;
+use crate::pen::{input_to_virtual, DrawingActivity};
+
+// Event codes
+const EV_SYN: u16 = 0;
+const EV_KEY: u16 = 1;
+const EV_ABS: u16 = 3;
+const SYN_REPORT: u16 = 0;
+const BTN_TOOL_PEN: u16 = 320;
+const BTN_TOUCH: u16 = 330;
+const ABS_X: u16 = 0;
+const ABS_Y: u16 = 1;
+const ABS_PRESSURE: u16 = 24;
+const ABS_DISTANCE: u16 = 25;
+
+/// One sample of a stroke the user drew, in virtual coordinates
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct PenPoint {
+    pub x: i32,
+    pub y: i32,
+    pub pressure: i32,
+    /// Seconds since the epoch
+    pub time: f64,
+}
+
+/// Everything drawn between putting the pen on the screen and lifting it
+#[derive(Debug, Clone, Serialize)]
+pub struct PenStroke {
+    pub points: Vec<PenPoint>,
+}
+
+impl PenStroke {
+    /// The (x, y, width, height) box around the stroke
+    pub fn bounds(&self) -> (i32, i32, i32, i32) {
+        bounds_of(self.points.iter())
+    }
+
+    pub fn start_time(&self) -> f64 {
+        self.points.first().map(|point| point.time).unwrap_or(0.0)
+    }
+
+    pub fn end_time(&self) -> f64 {
+        self.points.last().map(|point| point.time).unwrap_or(0.0)
+    }
+}
+
+/// The (x, y, width, height) box around some points
+pub fn bounds_of<'a>(points: impl Iterator<Item = &'a PenPoint>) -> (i32, i32, i32, i32) {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (i32::MAX, i32::MAX, i32::MIN, i32::MIN);
+    for point in points {
+        min_x = min_x.min(point.x);
+        min_y = min_y.min(point.y);
+        max_x = max_x.max(point.x);
+        max_y = max_y.max(point.y);
+    }
+    if min_x > max_x {
+        return (0, 0, 0, 0);
+    }
+    (min_x, min_y, max_x - min_x, max_y - min_y)
+}
+
+/// Whether the user's stylus is hovering over or touching the screen, shared with the touch
+/// listener so it can ignore the hand resting on the screen while writing
+#[derive(Debug, Clone, Default)]
+pub struct PenProximity {
+    in_range: Arc<AtomicBool>,
+    last_seen: Arc<Mutex<Option<Instant>>>,
+}
+
+impl PenProximity {
+    fn set_in_range(&self, in_range: bool) {
+        self.in_range.store(in_range, Ordering::SeqCst);
+        self.seen();
+    }
+
+    fn seen(&self) {
+        *self.last_seen.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// True if the pen is in range now or left it less than `grace` ago
+    pub fn near_within(&self, grace: Duration) -> bool {
+        self.in_range.load(Ordering::SeqCst) || self.last_seen.lock().unwrap().is_some_and(|seen| seen.elapsed() < grace)
+    }
+}
+
+/// Reads what the user draws with the stylus.
+///
+/// `Pen` only writes to the pen device; this opens it a second time for reading, on a
+/// background thread that hands over each stroke once the pen is lifted. Strokes that start
+/// while `Pen` is drawing, or just after, are ghostwriter's own and are dropped.
+pub struct PenInput {
+    strokes: Option<Receiver<PenStroke>>,
+    proximity: PenProximity,
+}
+
+impl PenInput {
+    /// Read strokes from the pen device at `device_path`, or nothing if there is none
+    pub fn new(device_path: Option<&str>, drawing: DrawingActivity) -> Result<Self> {
+        let Some(device_path) = device_path else {
+            return Ok(Self {
+                strokes: None,
+                proximity: PenProximity::default(),
+            });
+        };
+
+        let device_model = DeviceModel::detect();
+        info!("Reading pen strokes from {}", device_path);
+        let device = Device::open(device_path).map_err(|e| anyhow::anyhow!("Failed to open pen device {} for reading: {}", device_path, e))?;
+
+        let (sender, receiver) = channel();
+        let proximity = PenProximity::default();
+        let thread_proximity = proximity.clone();
+        std::thread::spawn(move || read_strokes(device, device_model, drawing, thread_proximity, sender));
+        Ok(Self {
+            strokes: Some(receiver),
+            proximity,
+        })
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.strokes.is_some()
+    }
+
+    /// Tracks the stylus being near the screen; never near if we aren't listening
+    pub fn proximity(&self) -> PenProximity {
+        self.proximity.clone()
+    }
+
+    /// Strokes finished since the last call, waiting up to `timeout` for the first one
+    pub fn poll_strokes(&self, timeout: Duration) -> Result<Vec<PenStroke>> {
+        let Some(strokes) = &self.strokes else {
+            std::thread::sleep(timeout);
+            return Ok(Vec::new());
+        };
+        let first = match strokes.recv_timeout(timeout) {
+            Ok(stroke) => stroke,
+            Err(RecvTimeoutError::Timeout) => return Ok(Vec::new()),
+            Err(RecvTimeoutError::Disconnected) => return Err(anyhow::anyhow!("Pen reader stopped")),
+        };
+        let mut result = vec![first];
+        result.extend(strokes.try_iter());
+        Ok(result)
+    }
+
+    /// Strokes finished since the last call, without waiting
+    pub fn take_pending(&self) -> Vec<PenStroke> {
+        match &self.strokes {
+            Some(strokes) => strokes.try_iter().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Body of the reader thread: collect samples between pen-down and pen-up into strokes,
+/// and keep track of whether the user's pen is near the screen
+fn read_strokes(mut device: Device, device_model: DeviceModel, drawing: DrawingActivity, proximity: PenProximity, sender: Sender<PenStroke>) {
+    let (mut raw_x, mut raw_y, mut pressure) = (0, 0, 0);
+    let mut touching = false;
+    let mut points = Vec::new();
+    let mut our_stroke = false;
+
+    loop {
+        let events = match device.fetch_events() {
+            Ok(events) => events.collect::<Vec<_>>(),
+            Err(e) => {
+                error!("Failed to read pen events: {}", e);
+                return;
+            }
+        };
+
+        for event in events {
+            let time = event.timestamp().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+            match (event.event_type().0, event.code()) {
+                (EV_ABS, ABS_X) => raw_x = event.value(),
+                (EV_ABS, ABS_Y) => raw_y = event.value(),
+                (EV_ABS, ABS_PRESSURE) => pressure = event.value(),
+                (EV_KEY, BTN_TOUCH) => touching = event.value() != 0,
+                // Our own drawing moves the pen in and out of range too
+                (EV_KEY, BTN_TOOL_PEN) if !drawing.is_ours(time) => proximity.set_in_range(event.value() != 0),
+                (EV_ABS, ABS_DISTANCE) if !drawing.is_ours(time) => proximity.seen(),
+                (EV_SYN, SYN_REPORT) => {
+                    if touching {
+                        if points.is_empty() {
+                            our_stroke = drawing.is_ours(time);
+                        }
+                        let (x, y) = input_to_virtual(device_model, (raw_x, raw_y));
+                        points.push(PenPoint { x, y, pressure, time });
+                    } else if !points.is_empty() {
+                        let stroke = PenStroke {
+                            points: std::mem::take(&mut points),
+                        };
+                        if our_stroke {
+                            continue;
+                        }
+                        trace!("Pen stroke with {} points in {:?}", stroke.points.len(), stroke.bounds());
+                        if sender.send(stroke).is_err() {
+                            return;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+use crate::pen_input::{bounds_of, PenPoint, PenStroke};
+use crate::touch::TriggerZone;
+
+// Symbols smaller than this (in virtual pixels) are more likely to be handwriting
+const MIN_SYMBOL_SIZE: f64 = 20.0;
+// How long after the first stroke of an "x" the second one may start
+const X_STROKE_GAP_SECS: f64 = 2.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PenSymbol {
+    Check,
+    Circle,
+    X,
+}
+
+impl PenSymbol {
+    pub fn name(&self) -> &str {
+        match self {
+            PenSymbol::Check => "check",
+            PenSymbol::Circle => "circle",
+            PenSymbol::X => "x",
+        }
+    }
+}
+
+/// Triggers ghostwriter when `symbol` is drawn inside the zone, from `[[pen_triggers]]` in the config file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PenTrigger {
+    pub symbol: PenSymbol,
+    #[serde(flatten)]
+    pub zone: TriggerZone,
+}
+
+impl PenTrigger {
+    pub fn validate(&self) -> Result<()> {
+        self.zone.validate()
+    }
+
+    /// The trigger zone that fired, named after the symbol unless the config names it
+    pub fn fired_zone(&self) -> TriggerZone {
+        TriggerZone {
+            name: Some(
+                self.zone
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("{} in {}", self.symbol.name(), self.zone.name())),
+            ),
+            ..self.zone.clone()
+        }
+    }
+}
+
+/// Looks for trigger symbols in the strokes the user draws
+#[derive(Default)]
+pub struct PenSymbolRecognizer {
+    // An "x" takes two strokes, so remember the last one
+    previous: Option<PenStroke>,
+}
+
+impl PenSymbolRecognizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a finished stroke, returning a symbol and its (x, y, width, height) box if it completed one
+    pub fn push(&mut self, stroke: &PenStroke) -> Option<(PenSymbol, (i32, i32, i32, i32))> {
+        let previous = self.previous.replace(stroke.clone());
+
+        if let Some(previous) = previous {
+            if stroke.start_time() - previous.end_time() <= X_STROKE_GAP_SECS && is_x(&previous.points, &stroke.points) {
+                self.previous = None;
+                let bounds = bounds_of(previous.points.iter().chain(stroke.points.iter()));
+                return Some((PenSymbol::X, bounds));
+            }
+        }
+
+        let symbol = if is_circle(&stroke.points) {
+            PenSymbol::Circle
+        } else if is_check(&stroke.points) {
+            PenSymbol::Check
+        } else {
+            return None;
+        };
+        debug!("Recognized a pen {} at {:?}", symbol.name(), stroke.bounds());
+        Some((symbol, stroke.bounds()))
+    }
+
+    /// Find the first trigger whose symbol was just drawn inside its zone
+    pub fn matching_trigger<'a>(&mut self, stroke: &PenStroke, triggers: &'a [PenTrigger]) -> Option<&'a PenTrigger> {
+        let (symbol, (x, y, width, height)) = self.push(stroke)?;
+        let (center_x, center_y) = (x + width / 2, y + height / 2);
+        triggers
+            .iter()
+            .find(|trigger| trigger.symbol == symbol && trigger.zone.contains(center_x, center_y))
+    }
+}
+
+fn distance(a: &PenPoint, b: &PenPoint) -> f64 {
+    (((a.x - b.x).pow(2) + (a.y - b.y).pow(2)) as f64).sqrt()
+}
+
+fn path_length(points: &[PenPoint]) -> f64 {
+    points.windows(2).map(|pair| distance(&pair[0], &pair[1])).sum()
+}
+
+/// A stroke that doesn't wander far from the line between its ends
+fn is_straight(points: &[PenPoint]) -> bool {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return false;
+    };
+    let chord = distance(first, last);
+    chord >= MIN_SYMBOL_SIZE && path_length(points) <= 1.3 * chord
+}
+
+/// A closed loop that turns through roughly a full revolution
+fn is_circle(points: &[PenPoint]) -> bool {
+    let (_, _, width, height) = bounds_of(points.iter());
+    let (width, height) = (width as f64, height as f64);
+    if points.len() < 8 || width < MIN_SYMBOL_SIZE || height < MIN_SYMBOL_SIZE {
+        return false;
+    }
+    if width / height > 2.5 || height / width > 2.5 {
+        return false;
+    }
+    if distance(&points[0], &points[points.len() - 1]) > 0.3 * width.max(height) {
+        return false;
+    }
+
+    // Add up how much the direction changes, ignoring jitter from tiny segments
+    let directions: Vec<f64> = points
+        .windows(2)
+        .filter(|pair| distance(&pair[0], &pair[1]) >= 2.0)
+        .map(|pair| ((pair[1].y - pair[0].y) as f64).atan2((pair[1].x - pair[0].x) as f64))
+        .collect();
+    let turning: f64 = directions
+        .windows(2)
+        .map(|pair| {
+            let mut change = pair[1] - pair[0];
+            if change > PI {
+                change -= 2.0 * PI;
+            } else if change < -PI {
+                change += 2.0 * PI;
+            }
+            change
+        })
+        .sum();
+    turning.abs() >= 1.5 * PI
+}
+
+/// A short stroke down and to the right, then a longer one up and to the right
+fn is_check(points: &[PenPoint]) -> bool {
+    if points.len() < 3 {
+        return false;
+    }
+    // The bottom of the check is the lowest point on the screen
+    let (vertex, _) = points.iter().enumerate().max_by_key(|(_, point)| point.y).unwrap();
+    if vertex == 0 || vertex == points.len() - 1 {
+        return false;
+    }
+    let (start, low, end) = (&points[0], &points[vertex], &points[points.len() - 1]);
+    let goes_down_right = low.x > start.x && low.y > start.y;
+    let goes_up_right = end.x > low.x && end.y < start.y;
+    let left_arm = distance(start, low);
+    let right_arm = distance(low, end);
+
+    goes_down_right
+        && goes_up_right
+        && right_arm >= MIN_SYMBOL_SIZE
+        && right_arm >= 1.3 * left_arm
+        && path_length(&points[..=vertex]) <= 1.4 * left_arm.max(1.0)
+        && path_length(&points[vertex..]) <= 1.4 * right_arm
+}
+
+/// Two straight diagonal strokes, sloping opposite ways, that cross
+fn is_x(first: &[PenPoint], second: &[PenPoint]) -> bool {
+    if !is_straight(first) || !is_straight(second) {
+        return false;
+    }
+    let diagonal = |points: &[PenPoint]| {
+        let (a, b) = (&points[0], &points[points.len() - 1]);
+        let (dx, dy) = ((b.x - a.x) as f64, (b.y - a.y) as f64);
+        let chord = distance(a, b);
+        (dx.abs() >= 0.4 * chord && dy.abs() >= 0.4 * chord).then_some((dx * dy).signum())
+    };
+    let (Some(first_slope), Some(second_slope)) = (diagonal(first), diagonal(second)) else {
+        return false;
+    };
+    first_slope != second_slope && segments_cross(&first[0], &first[first.len() - 1], &second[0], &second[second.len() - 1])
+}
+
+fn segments_cross(a1: &PenPoint, a2: &PenPoint, b1: &PenPoint, b2: &PenPoint) -> bool {
+    let side = |p: &PenPoint, q: &PenPoint, r: &PenPoint| ((q.x - p.x) as i64 * (r.y - p.y) as i64 - (q.y - p.y) as i64 * (r.x - p.x) as i64).signum();
+    side(a1, a2, b1) != side(a1, a2, b2) && side(b1, b2, a1) != side(b1, b2, a2)
+}
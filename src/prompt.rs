@@ -0,0 +1,168 @@
+use anyhow::Result;
+use base64::prelude::*;
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value as json;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::embedded_assets::{load_asset_bytes, load_config};
+use crate::llm_engine::Example;
+
+/// Values for `{{name}}` placeholders in prompt and tool files, filled in at trigger time
+pub type TemplateVariables = HashMap<String, String>;
+
+/// A few-shot example in a prompt pack. Paths are relative to the prompt file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PromptExample {
+    /// The example input screenshot
+    pub image: String,
+    /// A `{"function": ..., "arguments": ...}` file, like the `result.json` files in `evaluations/`
+    pub result: Option<String>,
+    /// ... or the ideal tool call given inline
+    pub tool: Option<String>,
+    pub arguments: Option<json>,
+}
+
+/// A prompt file, like `prompts/general.json`.
+///
+/// Besides the prompt text and tool list, a prompt can say which engine and model
+/// settings it works best with. Anything left out falls back to the `Config` values.
+/// It can also list worked examples that are stored next to it in a prompt-pack directory.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Prompt {
+    #[serde(skip)]
+    pub filename: String,
+    pub prompt: String,
+    pub tools: Option<Vec<String>>,
+    pub engine: Option<String>,
+    pub model: Option<String>,
+    pub thinking: Option<bool>,
+    pub thinking_tokens: Option<u32>,
+    pub web_search: Option<bool>,
+    pub max_tokens: Option<u32>,
+    pub tool_choice: Option<String>,
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub examples: Vec<PromptExample>,
+}
+
+impl Prompt {
+    pub fn load(filename: &str) -> Result<Self> {
+        let raw = load_config(filename)?;
+        let mut prompt: Self = serde_json::from_str(&raw).map_err(|e| anyhow::anyhow!("Invalid prompt file '{}': {}", filename, e))?;
+        prompt.filename = filename.to_string();
+        // Catch unknown tools now rather than part way through an interaction
+        for tool_name in prompt.tool_names() {
+            load_tool_definition(&tool_name).map_err(|e| anyhow::anyhow!("Prompt '{}': {}", filename, e))?;
+        }
+        Ok(prompt)
+    }
+
+    /// Load the example screenshots and tool calls this prompt refers to, failing on the first
+    /// one that can't be read
+    pub fn load_examples(&self) -> Result<Vec<Example>> {
+        self.examples.iter().map(|example| self.load_example(example)).collect()
+    }
+
+    /// Like `load_examples`, but skip any example that can't be read (with a warning), so a file
+    /// that went missing since startup doesn't stop an interaction
+    pub fn usable_examples(&self) -> Vec<Example> {
+        self.examples
+            .iter()
+            .filter_map(|example| {
+                self.load_example(example)
+                    .inspect_err(|e| warn!("Skipping example in prompt '{}': {}", self.filename, e))
+                    .ok()
+            })
+            .collect()
+    }
+
+    fn load_example(&self, example: &PromptExample) -> Result<Example> {
+        let image = load_asset_bytes(&self.relative_path(&example.image))
+            .ok_or_else(|| anyhow::anyhow!("Example image '{}' not found for prompt '{}'", example.image, self.filename))?;
+
+        let (tool_name, arguments) = match (&example.result, &example.tool, &example.arguments) {
+            (Some(result), _, _) => {
+                let raw = load_asset_bytes(&self.relative_path(result))
+                    .ok_or_else(|| anyhow::anyhow!("Example result '{}' not found for prompt '{}'", result, self.filename))?;
+                let result_json = serde_json::from_slice::<json>(&raw)?;
+                let tool_name = result_json["function"]
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Example result '{}' missing 'function'", result))?
+                    .to_string();
+                (tool_name, result_json["arguments"].clone())
+            }
+            (None, Some(tool), Some(arguments)) => (tool.clone(), arguments.clone()),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Example '{}' in prompt '{}' needs either 'result' or 'tool' and 'arguments'",
+                    example.image,
+                    self.filename
+                ))
+            }
+        };
+
+        Ok(Example {
+            base64_image: BASE64_STANDARD.encode(image),
+            tool_name,
+            arguments,
+        })
+    }
+
+    fn relative_path(&self, path: &str) -> String {
+        let base = Path::new(&self.filename).parent().unwrap_or(Path::new(""));
+        base.join(path).to_string_lossy().to_string()
+    }
+
+    /// The tools this prompt asks for; prompts that don't say get the classic drawing tools
+    pub fn tool_names(&self) -> Vec<String> {
+        self.tools.clone().unwrap_or_else(|| vec!["draw_text".to_string(), "draw_svg".to_string()])
+    }
+}
+
+/// The definition of a tool, from `tool_<name>.json`
+pub fn load_tool_definition(tool_name: &str) -> Result<json> {
+    let filename = format!("tool_{}.json", tool_name);
+    let raw = load_config(&filename).map_err(|e| anyhow::anyhow!("Unknown tool '{}': {}", tool_name, e))?;
+    serde_json::from_str(&raw).map_err(|e| anyhow::anyhow!("Invalid tool file '{}': {}", filename, e))
+}
+
+/// Replace `{{name}}` placeholders; unknown names are left in place so they're easy to spot
+pub fn render_template(template: &str, variables: &TemplateVariables) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after_open = &rest[start + 2..];
+        match after_open.find("}}") {
+            Some(end) => {
+                let name = after_open[..end].trim();
+                match variables.get(name) {
+                    Some(value) => output.push_str(value),
+                    None => {
+                        warn!("Unknown template variable '{}'", name);
+                        output.push_str(&rest[start..start + 2 + end + 2]);
+                    }
+                }
+                rest = &after_open[end + 2..];
+            }
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Render every string inside a JSON document, such as a tool definition
+pub fn render_json_template(value: &mut json, variables: &TemplateVariables) {
+    match value {
+        json::String(s) => *s = render_template(s, variables),
+        json::Array(items) => items.iter_mut().for_each(|item| render_json_template(item, variables)),
+        json::Object(map) => map.values_mut().for_each(|item| render_json_template(item, variables)),
+        _ => {}
+    }
+}
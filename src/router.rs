@@ -0,0 +1,66 @@
+use anyhow::Result;
+use log::{debug, info};
+use serde_json::json;
+use serde_json::Value as json;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::config::Route;
+use crate::llm_engine::LLMEngine;
+
+/// Ask a (preferably cheap) model which kind of page this is, and return the matching route
+pub fn select_route<'a>(engine: &mut dyn LLMEngine, routes: &'a [Route], base64_image: &str) -> Result<&'a Route> {
+    let route_names = routes.iter().map(|route| route.name.clone()).collect::<Vec<_>>();
+    let selected = Rc::new(RefCell::new(None::<String>));
+    let selected_clone = Rc::clone(&selected);
+
+    engine.register_tool(
+        "select_route",
+        json!({
+            "name": "select_route",
+            "description": "Pick the kind of assistant that should handle this page",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "route": {
+                        "type": "string",
+                        "enum": route_names,
+                        "description": "Which kind of page this is"
+                    }
+                },
+                "required": ["route"]
+            }
+        }),
+        Box::new(move |arguments: json| {
+            *selected_clone.borrow_mut() = arguments["route"].as_str().map(String::from);
+            None
+        }),
+    );
+
+    let route_list = routes
+        .iter()
+        .map(|route| format!("- {}: {}", route.name, route.description))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    engine.add_image_content(base64_image);
+    engine.add_text_content(&format!(
+        "This is a screenshot of a handwritten notebook page. Decide what the writer wants help with, \
+         focusing on the most recent writing, and call select_route with one of these:\n\n{}",
+        route_list
+    ));
+
+    debug!("Asking the router model to classify the page");
+    engine.execute()?;
+
+    let selected = selected
+        .borrow()
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("Router model did not select a route"))?;
+    let route = routes
+        .iter()
+        .find(|route| route.name == selected)
+        .ok_or_else(|| anyhow::anyhow!("Router model picked unknown route '{}'", selected))?;
+    info!("Router picked '{}', using prompt {}", route.name, route.prompt);
+    Ok(route)
+}
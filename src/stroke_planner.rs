@@ -0,0 +1,352 @@
+use log::debug;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::pen::{DrawSpeed, Stroke};
+use crate::svg_paths::Polyline;
+
+// Strokes shorter than this (in virtual pixels, at a raster scale of 1) don't leave a visible mark
+const MIN_STROKE_LENGTH: f32 = 0.5;
+// Strokes whose ends are closer than this (scaled the same way) are drawn as one
+const MERGE_DISTANCE: f32 = 0.5;
+// Side of the grid cells used to find the nearest stroke end
+const GRID_CELL_SIZE: f32 = 16.0;
+// How far ahead 2-opt looks for a better order, and how many times it goes over the plan
+const TWO_OPT_WINDOW: usize = 50;
+const TWO_OPT_PASSES: usize = 2;
+// Rough cost of sending the events, for the estimate: lifting and lowering the pen around a
+// stroke, and each move while it's down; the speed profile's pauses come on top
+const STROKE_OVERHEAD: Duration = Duration::from_micros(500);
+const MOVE_TIME: Duration = Duration::from_micros(150);
+// Pen transitions per stroke: lifted (in case it's down), lowered, lifted
+const TRANSITIONS_PER_STROKE: u32 = 3;
+// Shortest run of a bitmap row, in virtual pixels, that skipping scanlines leaves out
+const MIN_SKIPPED_RUN: usize = 16;
+
+/// Put strokes in an order that keeps the pen's travel between them short.
+///
+/// Degenerate strokes are dropped, then strokes are chained nearest-first (starting from the
+/// top left, and drawing a stroke backwards if its far end is closer), the order is tidied up
+/// with 2-opt, and strokes in the same style that end where the next one starts are joined.
+/// `scale` is the pixels per virtual pixel of bitmap runs (1 otherwise); finer rasters keep
+/// shorter strokes and shorter gaps, so a single pixel is never dropped or bridged over.
+pub fn plan_strokes(strokes: Vec<Stroke>, scale: u32) -> Vec<Stroke> {
+    let min_length = MIN_STROKE_LENGTH / scale as f32;
+    let merge_distance = MERGE_DISTANCE / scale as f32;
+    let strokes: Vec<Stroke> = strokes.into_iter().filter_map(|stroke| clean_stroke(stroke, min_length)).collect();
+    if strokes.is_empty() {
+        return strokes;
+    }
+
+    let mut order = nearest_neighbor_order(&strokes);
+    let before = travel_distance(&strokes, &order);
+    two_opt(&strokes, &mut order);
+    debug!(
+        "Planned {} strokes, pen travel {:.0}px (nearest neighbor) -> {:.0}px (2-opt)",
+        strokes.len(),
+        before,
+        travel_distance(&strokes, &order)
+    );
+
+    let mut strokes: Vec<Option<Stroke>> = strokes.into_iter().map(Some).collect();
+    let mut planned: Vec<Stroke> = Vec::with_capacity(order.len());
+    for (index, reversed) in order {
+        let mut stroke = strokes[index].take().unwrap();
+        if reversed {
+            stroke.points.reverse();
+        }
+        match planned.last_mut() {
+            Some(last) if last.style == stroke.style && distance(*last.points.last().unwrap(), stroke.points[0]) <= merge_distance => {
+                last.points.extend(stroke.points.into_iter().skip(1))
+            }
+            _ => planned.push(stroke),
+        }
+    }
+    planned
+}
+
+/// Roughly how long the pen takes to draw the strokes at `speed`, moving in small steps
+/// (`interpolated`) or straight from point to point
+pub fn estimate_draw_time(strokes: &[Stroke], interpolated: bool, speed: DrawSpeed) -> Duration {
+    let moves: f32 = if interpolated {
+        strokes.iter().map(|stroke| (stroke_length(&stroke.points) / speed.step()).ceil()).sum()
+    } else {
+        strokes.iter().map(|stroke| (stroke.points.len() - 1) as f32).sum()
+    };
+    (STROKE_OVERHEAD + speed.transition_pause() * TRANSITIONS_PER_STROKE) * strokes.len() as u32 + (MOVE_TIME + speed.move_pause()).mul_f32(moves)
+}
+
+/// The runs of inked pixels in each row of a bitmap with `scale` pixels per virtual pixel, as
+/// straight strokes in virtual coordinates.
+///
+/// With `skip_scanlines`, long runs on odd rows are left out where the row above is inked all
+/// along them, so big filled areas take half the strokes while thin lines keep every pixel.
+pub fn bitmap_runs(bitmap: &[Vec<bool>], skip_scanlines: bool, scale: u32) -> Vec<Polyline> {
+    let min_skipped_run = MIN_SKIPPED_RUN * scale as usize;
+    let to_virtual = |x: usize, y: usize| (x as f32 / scale as f32, y as f32 / scale as f32);
+    let mut runs = Vec::new();
+    for (y, row) in bitmap.iter().enumerate() {
+        let mut start = None;
+        for x in 0..=row.len() {
+            match (row.get(x).copied().unwrap_or(false), start) {
+                (true, None) => start = Some(x),
+                (false, Some(first)) => {
+                    start = None;
+                    if skip_scanlines
+                        && y % 2 == 1
+                        && x - first >= min_skipped_run
+                        && bitmap[y - 1].get(first..x).is_some_and(|above| above.iter().all(|&pixel| pixel))
+                    {
+                        continue;
+                    }
+                    // Reach the right edge of the last pixel
+                    runs.push(vec![to_virtual(first, y), to_virtual(x, y)]);
+                }
+                _ => {}
+            }
+        }
+    }
+    runs
+}
+
+/// Drop repeated points, and the whole stroke if it is shorter than `min_length`
+fn clean_stroke(stroke: Stroke, min_length: f32) -> Option<Stroke> {
+    let mut cleaned: Polyline = Vec::with_capacity(stroke.points.len());
+    for point in stroke.points {
+        if cleaned.last().is_none_or(|&last| last != point) {
+            cleaned.push(point);
+        }
+    }
+    (cleaned.len() > 1 && stroke_length(&cleaned) >= min_length).then_some(Stroke {
+        points: cleaned,
+        style: stroke.style,
+    })
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt()
+}
+
+fn stroke_length(stroke: &[(f32, f32)]) -> f32 {
+    stroke.windows(2).map(|pair| distance(pair[0], pair[1])).sum()
+}
+
+/// Where a stroke starts and ends, taking the direction it will be drawn in into account
+fn ends(strokes: &[Stroke], (index, reversed): (usize, bool)) -> ((f32, f32), (f32, f32)) {
+    let stroke = &strokes[index].points;
+    let (first, last) = (stroke[0], stroke[stroke.len() - 1]);
+    if reversed {
+        (last, first)
+    } else {
+        (first, last)
+    }
+}
+
+fn travel_distance(strokes: &[Stroke], order: &[(usize, bool)]) -> f32 {
+    order.windows(2).map(|pair| distance(ends(strokes, pair[0]).1, ends(strokes, pair[1]).0)).sum()
+}
+
+fn grid_cell((x, y): (f32, f32)) -> (i32, i32) {
+    ((x / GRID_CELL_SIZE).floor() as i32, (y / GRID_CELL_SIZE).floor() as i32)
+}
+
+/// Greedily pick the stroke with an end nearest to where the pen is, as (index, reversed)
+fn nearest_neighbor_order(strokes: &[Stroke]) -> Vec<(usize, bool)> {
+    // Both ends of every stroke not drawn yet, by grid cell
+    let mut grid: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+    for (index, stroke) in strokes.iter().enumerate() {
+        for end in [stroke.points[0], stroke.points[stroke.points.len() - 1]] {
+            grid.entry(grid_cell(end)).or_default().push(index);
+        }
+    }
+    let max_ring = grid
+        .keys()
+        .map(|&(x, y)| x.abs().max(y.abs()))
+        .max()
+        .unwrap_or(0)
+        .saturating_mul(2)
+        .saturating_add(1);
+
+    let mut order = Vec::with_capacity(strokes.len());
+    let mut position = (0.0, 0.0);
+    while order.len() < strokes.len() {
+        let (cx, cy) = grid_cell(position);
+        let mut best: Option<(f32, usize, bool)> = None;
+        for ring in 0..=max_ring {
+            // Nothing further out can beat what we have
+            if best.is_some_and(|(best_distance, _, _)| best_distance <= (ring - 1).max(0) as f32 * GRID_CELL_SIZE) {
+                break;
+            }
+            for dx in -ring..=ring {
+                for dy in -ring..=ring {
+                    if dx.abs() != ring && dy.abs() != ring {
+                        continue;
+                    }
+                    for &index in grid.get(&(cx + dx, cy + dy)).into_iter().flatten() {
+                        for reversed in [false, true] {
+                            let d = distance(position, ends(strokes, (index, reversed)).0);
+                            if best.is_none_or(|(best_distance, _, _)| d < best_distance) {
+                                best = Some((d, index, reversed));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let Some((_, index, reversed)) = best else {
+            break;
+        };
+        let stroke = &strokes[index];
+        for end in [stroke.points[0], stroke.points[stroke.points.len() - 1]] {
+            if let Some(cell) = grid.get_mut(&grid_cell(end)) {
+                if let Some(at) = cell.iter().position(|&other| other == index) {
+                    cell.swap_remove(at);
+                }
+            }
+        }
+        order.push((index, reversed));
+        position = ends(strokes, (index, reversed)).1;
+    }
+    order
+}
+
+/// Reverse runs of the order (and the strokes in them) wherever that shortens the travel
+fn two_opt(strokes: &[Stroke], order: &mut [(usize, bool)]) {
+    let count = order.len();
+    for _ in 0..TWO_OPT_PASSES {
+        let mut improved = false;
+        for i in 1..count {
+            for j in i + 1..count.min(i + TWO_OPT_WINDOW) {
+                let before_end = ends(strokes, order[i - 1]).1;
+                let (first_start, _) = ends(strokes, order[i]);
+                let (_, last_end) = ends(strokes, order[j]);
+                let after_start = order.get(j + 1).map(|&next| ends(strokes, next).0);
+
+                let current = distance(before_end, first_start) + after_start.map_or(0.0, |after| distance(last_end, after));
+                let reversed = distance(before_end, last_end) + after_start.map_or(0.0, |after| distance(first_start, after));
+                if reversed < current - 0.01 {
+                    order[i..=j].reverse();
+                    order[i..=j].iter_mut().for_each(|(_, reversed)| *reversed = !*reversed);
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fine_rasters_keep_single_pixel_runs() {
+        // Isolated pixels, so nothing merges
+        let bitmap: Vec<Vec<bool>> = (0..8).map(|y| (0..8).map(|x| x % 2 == 0 && y % 2 == 0).collect()).collect();
+        for scale in [1, 2, 3, 4] {
+            let runs: Vec<Stroke> = bitmap_runs(&bitmap, false, scale).into_iter().map(Stroke::from).collect();
+            assert_eq!(runs.len(), 16);
+            assert_eq!(plan_strokes(runs, scale).len(), 16, "scale {}", scale);
+        }
+    }
+
+    fn line(from: (f32, f32), to: (f32, f32)) -> Stroke {
+        vec![from, to].into()
+    }
+
+    /// Every point drawn, whichever stroke and direction it ended up in
+    fn sorted_points(strokes: &[Stroke]) -> Vec<(f32, f32)> {
+        let mut points: Vec<(f32, f32)> = strokes.iter().flat_map(|stroke| stroke.points.iter().copied()).collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        points
+    }
+
+    #[test]
+    fn degenerate_strokes_are_dropped() {
+        let strokes = vec![
+            line((10.0, 10.0), (50.0, 10.0)),
+            vec![(200.0, 200.0)].into(),
+            line((300.0, 300.0), (300.0, 300.0)),
+            line((400.0, 400.0), (400.2, 400.0)),
+            vec![(500.0, 500.0), (500.0, 500.0), (520.0, 500.0)].into(),
+        ];
+        let planned = plan_strokes(strokes, 1);
+        assert_eq!(planned.len(), 2);
+        assert_eq!(sorted_points(&planned), [(10.0, 10.0), (50.0, 10.0), (500.0, 500.0), (520.0, 500.0)]);
+    }
+
+    #[test]
+    fn strokes_are_reversed_when_their_far_end_is_closer() {
+        // The second stroke's far end is where the first one stops
+        let planned = plan_strokes(vec![line((0.0, 0.0), (10.0, 0.0)), line((200.0, 0.0), (20.0, 0.0))], 1);
+        assert_eq!(planned.len(), 2);
+        assert_eq!(planned[0].points, [(0.0, 0.0), (10.0, 0.0)]);
+        assert_eq!(planned[1].points, [(20.0, 0.0), (200.0, 0.0)]);
+    }
+
+    #[test]
+    fn planning_starts_at_the_top_left() {
+        let planned = plan_strokes(vec![line((500.0, 500.0), (600.0, 500.0)), line((50.0, 20.0), (5.0, 5.0))], 1);
+        assert_eq!(planned[0].points, [(5.0, 5.0), (50.0, 20.0)]);
+    }
+
+    #[test]
+    fn touching_strokes_in_the_same_style_are_joined() {
+        let planned = plan_strokes(vec![line((0.0, 0.0), (10.0, 0.0)), line((20.0, 0.0), (10.0, 0.0))], 1);
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].points, [(0.0, 0.0), (10.0, 0.0), (20.0, 0.0)]);
+
+        let mut eraser = line((10.0, 0.0), (20.0, 0.0));
+        eraser.style.eraser = true;
+        assert_eq!(plan_strokes(vec![line((0.0, 0.0), (10.0, 0.0)), eraser], 1).len(), 2);
+    }
+
+    #[test]
+    fn planning_keeps_every_stroke_and_shortens_travel() {
+        // A dash in each cell of a grid, jittered and shuffled with a fixed pseudo-random
+        // sequence; cells are far enough apart that no two dashes get joined
+        let mut seed = 12345u32;
+        let mut next = || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+        let mut strokes: Vec<Stroke> = (0..300)
+            .map(|cell| {
+                let (x, y) = ((cell % 15) as f32 * 45.0 + next() * 10.0, (cell / 15) as f32 * 45.0 + next() * 10.0);
+                line((x, y), (x + 5.0 + next() * 20.0, y + next() * 20.0))
+            })
+            .collect();
+        for i in (1..strokes.len()).rev() {
+            let j = (next() * (i + 1) as f32) as usize;
+            strokes.swap(i, j.min(i));
+        }
+        let source_order: Vec<(usize, bool)> = (0..strokes.len()).map(|index| (index, false)).collect();
+        let before = travel_distance(&strokes, &source_order);
+
+        let planned = plan_strokes(strokes.clone(), 1);
+        assert_eq!(planned.len(), strokes.len());
+        assert_eq!(sorted_points(&planned), sorted_points(&strokes));
+        let planned_order: Vec<(usize, bool)> = (0..planned.len()).map(|index| (index, false)).collect();
+        let after = travel_distance(&planned, &planned_order);
+        assert!(after < before / 5.0, "travel {} -> {}", before, after);
+    }
+
+    #[test]
+    fn two_opt_untangles_a_crossed_order() {
+        // Drawn 0, 2, 1, 3 along a line; 2-opt should swap the middle pair back
+        let strokes = vec![
+            line((0.0, 0.0), (1.0, 0.0)),
+            line((10.0, 0.0), (11.0, 0.0)),
+            line((20.0, 0.0), (21.0, 0.0)),
+            line((30.0, 0.0), (31.0, 0.0)),
+        ];
+        let mut order = vec![(0, false), (2, false), (1, false), (3, false)];
+        let before = travel_distance(&strokes, &order);
+        two_opt(&strokes, &mut order);
+        assert!(travel_distance(&strokes, &order) < before);
+        assert_eq!(order.iter().map(|&(index, _)| index).collect::<Vec<_>>(), [0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,737 @@
+use anyhow::Result;
+use log::debug;
+use resvg::usvg::tiny_skia_path::{PathSegment, Point};
+use resvg::usvg::{FillRule, Group, Node, Paint, Path, Transform};
+use std::f32::consts::PI;
+
+use crate::pen::{PenStyle, Stroke};
+use crate::util::parse_svg;
+
+// How far (in virtual pixels) a flattened curve may stray from the real one
+const FLATNESS_TOLERANCE: f32 = 0.25;
+// Deepest curve subdivision, in case a curve is degenerate
+const MAX_SUBDIVISION_DEPTH: u32 = 16;
+// Paint lighter than this is paper colored, like a white background, and isn't drawn
+const MAX_INK_LUMINANCE: f32 = 0.9;
+// Fills at least this dark get the second set of lines in a cross-hatch
+const CROSS_HATCH_DARKNESS: f32 = 0.5;
+// Parallel hatching runs at this angle, in degrees; cross-hatching adds the opposite one
+const HATCH_ANGLE: f32 = 45.0;
+// Contour hatching gives up after this many rings, in case a shape never closes up
+const MAX_CONTOUR_RINGS: usize = 500;
+// Width, in virtual pixels, of a line drawn at full pressure; thinner SVG strokes press lighter,
+// down to MIN_PRESSURE, and wider ones are drawn as side by side passes, up to MAX_STROKE_PASSES
+const FULL_PRESSURE_WIDTH: f32 = 2.0;
+const MIN_PRESSURE: f32 = 0.3;
+const MAX_STROKE_PASSES: usize = 8;
+// Gap between the lines that fill in text, in virtual pixels; close enough to look solid
+const TEXT_FILL_SPACING: f32 = 1.0;
+
+/// A continuous pen stroke, in virtual coordinates
+pub type Polyline = Vec<(f32, f32)>;
+
+/// How filled shapes are shaded, from `--hatch-style`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HatchStyle {
+    /// Just the outline
+    None,
+    /// Diagonal lines
+    Parallel,
+    /// Diagonal lines both ways for dark fills
+    Cross,
+    /// Rings following the outline inward
+    Contour,
+}
+
+impl HatchStyle {
+    pub fn from_string(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(HatchStyle::None),
+            "parallel" => Ok(HatchStyle::Parallel),
+            "cross" => Ok(HatchStyle::Cross),
+            "contour" => Ok(HatchStyle::Contour),
+            _ => Err(anyhow::anyhow!("Invalid hatch style: {}. Use none, parallel, cross or contour", s)),
+        }
+    }
+}
+
+/// Hatching for filled shapes: `spacing` is the gap between lines, in virtual pixels, for a
+/// black fill; lighter fills get proportionally wider gaps
+#[derive(Debug, Clone, Copy)]
+pub struct Hatch {
+    pub style: HatchStyle,
+    pub spacing: f32,
+}
+
+/// Turn an SVG into the pen strokes that draw it.
+///
+/// Every visible stroked or filled path becomes one stroke per subpath, with its transforms
+/// applied and curves flattened. A path's `stroke-width` sets the pen pressure, or the number
+/// of passes for wide lines. Fills are outlined and shaded with `hatch`, except text, whose
+/// glyphs are always filled in solid so it stays readable. Strokes are split where they leave
+/// the `width` by `height` canvas.
+pub fn svg_to_strokes(svg_data: &str, width: u32, height: u32, hatch: Hatch) -> Result<Vec<Stroke>> {
+    let tree = parse_svg(svg_data, width, height)?;
+    let mut strokes = Vec::new();
+    collect_group(tree.root(), hatch, &mut strokes);
+
+    let strokes: Vec<Stroke> = strokes
+        .into_iter()
+        .flat_map(|stroke| {
+            let style = stroke.style;
+            clip_to_canvas(stroke.points, width as f32, height as f32)
+                .into_iter()
+                .map(move |points| Stroke { points, style })
+        })
+        .collect();
+    debug!(
+        "SVG flattened to {} strokes with {} points",
+        strokes.len(),
+        strokes.iter().map(|stroke| stroke.points.len()).sum::<usize>()
+    );
+    Ok(strokes)
+}
+
+fn collect_group(group: &Group, hatch: Hatch, strokes: &mut Vec<Stroke>) {
+    for node in group.children() {
+        match node {
+            Node::Group(group) => collect_group(group, hatch, strokes),
+            Node::Path(path) => collect_path(path, hatch, strokes),
+            // Hollow or hatched letters are hard to read, so glyphs get a solid fill whatever the
+            // hatch style
+            Node::Text(text) => collect_group(
+                text.flattened(),
+                Hatch {
+                    style: HatchStyle::Parallel,
+                    spacing: TEXT_FILL_SPACING,
+                },
+                strokes,
+            ),
+            Node::Image(_) => debug!("Skipping an image in the SVG; use --svg-raster to draw it"),
+        }
+    }
+}
+
+fn collect_path(path: &Path, hatch: Hatch, strokes: &mut Vec<Stroke>) {
+    if !path.is_visible() {
+        return;
+    }
+    let stroke_darkness = path.stroke().map_or(0.0, |stroke| darkness(stroke.paint(), stroke.opacity().get()));
+    let fill_darkness = path.fill().map_or(0.0, |fill| darkness(fill.paint(), fill.opacity().get()));
+    if stroke_darkness.max(fill_darkness) < 1.0 - MAX_INK_LUMINANCE {
+        return;
+    }
+
+    let outlines = flatten_path(path.data(), path.abs_transform());
+    if fill_darkness >= 1.0 - MAX_INK_LUMINANCE {
+        let fill_rule = path.fill().map_or(FillRule::NonZero, |fill| fill.rule());
+        let spacing = (hatch.spacing / fill_darkness).max(1.0);
+        let shading = match hatch.style {
+            HatchStyle::None => Vec::new(),
+            HatchStyle::Parallel => hatch_lines(&outlines, fill_rule, HATCH_ANGLE, spacing),
+            HatchStyle::Cross => {
+                let mut lines = hatch_lines(&outlines, fill_rule, HATCH_ANGLE, spacing);
+                if fill_darkness >= CROSS_HATCH_DARKNESS {
+                    lines.extend(hatch_lines(&outlines, fill_rule, -HATCH_ANGLE, spacing));
+                }
+                lines
+            }
+            HatchStyle::Contour => contour_lines(&outlines, fill_rule, spacing),
+        };
+        strokes.extend(shading.into_iter().map(Stroke::from));
+    }
+
+    match path.stroke() {
+        Some(stroke) if stroke_darkness >= 1.0 - MAX_INK_LUMINANCE => {
+            let transform = path.abs_transform();
+            let scale = (transform.sx * transform.sy - transform.kx * transform.ky).abs().sqrt();
+            strokes.extend(wide_strokes(&outlines, stroke.width().get() * scale));
+        }
+        _ => strokes.extend(outlines.into_iter().map(Stroke::from)),
+    }
+}
+
+/// Strokes that draw lines `width` virtual pixels wide along the outlines: one pass with lighter
+/// pressure for thin lines, or passes side by side at full pressure for wide ones
+fn wide_strokes(outlines: &[Polyline], width: f32) -> Vec<Stroke> {
+    if width <= FULL_PRESSURE_WIDTH {
+        let style = PenStyle {
+            pressure: (width / FULL_PRESSURE_WIDTH).max(MIN_PRESSURE),
+            ..PenStyle::default()
+        };
+        return outlines.iter().map(|points| Stroke { points: points.clone(), style }).collect();
+    }
+
+    let passes = ((width / FULL_PRESSURE_WIDTH).ceil() as usize).min(MAX_STROKE_PASSES);
+    let pass_spacing = width / passes as f32;
+    outlines
+        .iter()
+        .flat_map(|outline| (0..passes).map(move |pass| Stroke::from(offset_polyline(outline, (pass as f32 + 0.5) * pass_spacing - width / 2.0))))
+        .collect()
+}
+
+/// How much ink a paint puts down, from 0 (paper) to 1 (solid black). Gradients and patterns
+/// count as a mid gray.
+fn darkness(paint: &Paint, opacity: f32) -> f32 {
+    let darkness = match paint {
+        Paint::Color(color) => 1.0 - (0.2126 * color.red as f32 + 0.7152 * color.green as f32 + 0.0722 * color.blue as f32) / 255.0,
+        _ => 0.5,
+    };
+    darkness * opacity
+}
+
+/// One polyline per subpath, with the transform applied before flattening so the tolerance is in canvas units
+fn flatten_path(data: &resvg::usvg::tiny_skia_path::Path, transform: Transform) -> Vec<Polyline> {
+    let map = |point: Point| {
+        let mut points = [point];
+        transform.map_points(&mut points);
+        (points[0].x, points[0].y)
+    };
+
+    let mut polylines = Vec::new();
+    let mut current: Polyline = Vec::new();
+    let mut start = (0.0, 0.0);
+    for segment in data.segments() {
+        let last = current.last().copied().unwrap_or(start);
+        match segment {
+            PathSegment::MoveTo(point) => {
+                if current.len() > 1 {
+                    polylines.push(std::mem::take(&mut current));
+                }
+                start = map(point);
+                current = vec![start];
+            }
+            PathSegment::LineTo(point) => current.push(map(point)),
+            PathSegment::QuadTo(control, point) => {
+                // Raise to a cubic with the same shape
+                let (control, end) = (map(control), map(point));
+                let control1 = (last.0 + 2.0 / 3.0 * (control.0 - last.0), last.1 + 2.0 / 3.0 * (control.1 - last.1));
+                let control2 = (end.0 + 2.0 / 3.0 * (control.0 - end.0), end.1 + 2.0 / 3.0 * (control.1 - end.1));
+                flatten_cubic(last, control1, control2, end, 0, &mut current);
+            }
+            PathSegment::CubicTo(control1, control2, point) => {
+                flatten_cubic(last, map(control1), map(control2), map(point), 0, &mut current);
+            }
+            PathSegment::Close => {
+                if last != start {
+                    current.push(start);
+                }
+            }
+        }
+    }
+    if current.len() > 1 {
+        polylines.push(current);
+    }
+    polylines
+}
+
+/// Split the curve in half until each piece is flat enough to draw as a line, appending the
+/// points after `p0`
+fn flatten_cubic(p0: (f32, f32), p1: (f32, f32), p2: (f32, f32), p3: (f32, f32), depth: u32, points: &mut Polyline) {
+    if depth >= MAX_SUBDIVISION_DEPTH || distance_to_line(p1, p0, p3).max(distance_to_line(p2, p0, p3)) <= FLATNESS_TOLERANCE {
+        points.push(p3);
+        return;
+    }
+    let mid = |a: (f32, f32), b: (f32, f32)| ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+    let (p01, p12, p23) = (mid(p0, p1), mid(p1, p2), mid(p2, p3));
+    let (p012, p123) = (mid(p01, p12), mid(p12, p23));
+    let split = mid(p012, p123);
+    flatten_cubic(p0, p01, p012, split, depth + 1, points);
+    flatten_cubic(split, p123, p23, p3, depth + 1, points);
+}
+
+fn distance_to_line(point: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length < f32::EPSILON {
+        return ((point.0 - a.0).powi(2) + (point.1 - a.1).powi(2)).sqrt();
+    }
+    ((point.0 - a.0) * dy - (point.1 - a.1) * dx).abs() / length
+}
+
+/// Lines at `angle` degrees, `spacing` apart, across the inside of the shape. Every other line
+/// runs backwards so the pen zigzags instead of jumping back to the same side.
+fn hatch_lines(outlines: &[Polyline], fill_rule: FillRule, angle: f32, spacing: f32) -> Vec<Polyline> {
+    // Rotate the shape so the hatch lines are horizontal, then scan it line by line
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let rotate = |(x, y): (f32, f32)| (x * cos + y * sin, y * cos - x * sin);
+    let unrotate = |(x, y): (f32, f32)| (x * cos - y * sin, x * sin + y * cos);
+    let rotated: Vec<Polyline> = outlines.iter().map(|outline| outline.iter().copied().map(rotate).collect()).collect();
+
+    let (min_y, max_y) = rotated
+        .iter()
+        .flatten()
+        .fold((f32::MAX, f32::MIN), |(min, max), &(_, y)| (min.min(y), max.max(y)));
+    if min_y > max_y {
+        return Vec::new();
+    }
+
+    let mut lines = Vec::new();
+    // Line up with multiples of the spacing, so neighbouring shapes hatch seamlessly
+    let mut y = (min_y / spacing).ceil() * spacing;
+    let mut backwards = false;
+    while y <= max_y {
+        let mut row: Vec<Polyline> = inside_spans(&rotated, fill_rule, y)
+            .into_iter()
+            .map(|(start, end)| vec![unrotate((start, y)), unrotate((end, y))])
+            .collect();
+        if backwards {
+            row.reverse();
+            row.iter_mut().for_each(|line| line.reverse());
+        }
+        lines.extend(row);
+        backwards = !backwards;
+        y += spacing;
+    }
+    lines
+}
+
+/// The (start x, end x) spans of the horizontal line at `y` that are inside the shape
+fn inside_spans(outlines: &[Polyline], fill_rule: FillRule, y: f32) -> Vec<(f32, f32)> {
+    // Where each edge crosses the line, and which way it winds
+    let mut crossings: Vec<(f32, i32)> = Vec::new();
+    for outline in outlines {
+        for (i, &a) in outline.iter().enumerate() {
+            // Fills are closed shapes, whether or not the path closes itself
+            let b = outline[(i + 1) % outline.len()];
+            if (a.1 <= y && y < b.1) || (b.1 <= y && y < a.1) {
+                let x = a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1);
+                crossings.push((x, if b.1 > a.1 { 1 } else { -1 }));
+            }
+        }
+    }
+    crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut spans = Vec::new();
+    let mut winding = 0;
+    let mut span_start = None;
+    for (x, direction) in crossings {
+        winding += direction;
+        let inside = match fill_rule {
+            FillRule::NonZero => winding != 0,
+            FillRule::EvenOdd => winding % 2 != 0,
+        };
+        match (inside, span_start) {
+            (true, None) => span_start = Some(x),
+            (false, Some(start)) => {
+                if x > start {
+                    spans.push((start, x));
+                }
+                span_start = None;
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
+fn is_inside(outlines: &[Polyline], fill_rule: FillRule, (x, y): (f32, f32)) -> bool {
+    inside_spans(outlines, fill_rule, y).iter().any(|&(start, end)| start <= x && x <= end)
+}
+
+/// Rings `spacing` apart following each outline into the fill (inward for outlines, outward
+/// for holes), until they close up. Where a ring runs into another one or strays outside the
+/// shape, it is cut away.
+fn contour_lines(outlines: &[Polyline], fill_rule: FillRule, spacing: f32) -> Vec<Polyline> {
+    let mut rings = Vec::new();
+    for outline in outlines {
+        let mut points = outline.clone();
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        if points.len() < 3 {
+            continue;
+        }
+        let Some(side) = fill_side(outlines, fill_rule, &points) else {
+            continue;
+        };
+        let area = signed_area(&points);
+        for ring_number in 1..=MAX_CONTOUR_RINGS {
+            let distance = spacing * ring_number as f32;
+            let ring = offset_polygon(&points, side * distance);
+            // Shrunk past nothing: the ring has turned inside out or vanished
+            let ring_area = signed_area(&ring);
+            if ring_area.signum() != area.signum() || ring_area.abs() < spacing * spacing {
+                break;
+            }
+            // A real contour point is inside and at least `distance` from every edge
+            let keep = |point: (f32, f32)| is_inside(outlines, fill_rule, point) && distance_to_outlines(outlines, point) >= 0.9 * distance;
+            let mut kept_any = false;
+            let mut piece = Vec::new();
+            for point in densify(&ring, spacing / 2.0) {
+                if keep(point) {
+                    piece.push(point);
+                    kept_any = true;
+                } else if !piece.is_empty() {
+                    rings.push(std::mem::take(&mut piece));
+                }
+            }
+            if !piece.is_empty() {
+                rings.push(piece);
+            }
+            if !kept_any {
+                break;
+            }
+        }
+    }
+    rings.retain(|ring| ring.len() > 1);
+    rings
+}
+
+/// 1.0 if the fill is to the left of the outline's edges, -1.0 if it's to the right
+fn fill_side(outlines: &[Polyline], fill_rule: FillRule, points: &[(f32, f32)]) -> Option<f32> {
+    // Probe just beside the middle of the longest edge
+    let (a, b) = (0..points.len())
+        .map(|i| (points[i], points[(i + 1) % points.len()]))
+        .max_by(|(a1, b1), (a2, b2)| {
+            let length = |a: (f32, f32), b: (f32, f32)| (b.0 - a.0).powi(2) + (b.1 - a.1).powi(2);
+            length(*a1, *b1).total_cmp(&length(*a2, *b2))
+        })?;
+    let normal = left_normal(a, b);
+    let middle = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+    let probe = |side: f32| (middle.0 + side * 0.5 * normal.0, middle.1 + side * 0.5 * normal.1);
+    if is_inside(outlines, fill_rule, probe(1.0)) {
+        Some(1.0)
+    } else if is_inside(outlines, fill_rule, probe(-1.0)) {
+        Some(-1.0)
+    } else {
+        None
+    }
+}
+
+fn left_normal(a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length = (dx * dx + dy * dy).sqrt().max(f32::EPSILON);
+    (-dy / length, dx / length)
+}
+
+/// The closed polygon with extra points so no two are more than `step` apart
+fn densify(points: &[(f32, f32)], step: f32) -> Polyline {
+    let mut dense = Vec::new();
+    for i in 0..=points.len() {
+        let (a, b) = (points[i % points.len()], points[(i + 1) % points.len()]);
+        dense.push(a);
+        if i == points.len() {
+            break;
+        }
+        let steps = (((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt() / step).ceil() as usize;
+        for j in 1..steps {
+            let t = j as f32 / steps as f32;
+            dense.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+        }
+    }
+    dense
+}
+
+fn distance_to_outlines(outlines: &[Polyline], point: (f32, f32)) -> f32 {
+    outlines
+        .iter()
+        .flat_map(|outline| (0..outline.len()).map(move |i| (outline[i], outline[(i + 1) % outline.len()])))
+        .map(|(a, b)| distance_to_segment(point, a, b))
+        .fold(f32::MAX, f32::min)
+}
+
+fn distance_to_segment(point: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared < f32::EPSILON {
+        0.0
+    } else {
+        (((point.0 - a.0) * dx + (point.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+    ((point.0 - a.0 - dx * t).powi(2) + (point.1 - a.1 - dy * t).powi(2)).sqrt()
+}
+
+/// Twice the shoelace area; positive when the inside is to the left of each edge
+fn signed_area(points: &[(f32, f32)]) -> f32 {
+    (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum()
+}
+
+/// Move each edge `distance` to its left (right if negative). Corners where the edges move
+/// apart are joined with an arc, those where they overlap meet at a miter.
+fn offset_polygon(points: &[(f32, f32)], distance: f32) -> Vec<(f32, f32)> {
+    let count = points.len();
+    let mut offset = Vec::with_capacity(count);
+    for i in 0..count {
+        let (previous, point, next) = (points[(i + count - 1) % count], points[i], points[(i + 1) % count]);
+        let (n1, n2) = (left_normal(previous, point), left_normal(point, next));
+        let turn = (point.0 - previous.0) * (next.1 - point.1) - (point.1 - previous.1) * (next.0 - point.0);
+
+        if turn * distance < 0.0 {
+            // The edges move apart here, so go round the corner at `distance`
+            let (start, end) = (n1.1.atan2(n1.0), n2.1.atan2(n2.0));
+            let mut sweep = end - start;
+            if sweep > PI {
+                sweep -= 2.0 * PI;
+            } else if sweep < -PI {
+                sweep += 2.0 * PI;
+            }
+            let steps = ((sweep.abs() / (PI / 8.0)).ceil() as usize).max(1);
+            for step in 0..=steps {
+                let angle = start + sweep * step as f32 / steps as f32;
+                offset.push((point.0 + angle.cos() * distance, point.1 + angle.sin() * distance));
+            }
+            continue;
+        }
+
+        offset.push(miter_point(point, n1, n2, distance));
+    }
+    offset
+}
+
+/// A polyline moved `distance` to its left (negative for right); closed ones stay closed
+fn offset_polyline(points: &[(f32, f32)], distance: f32) -> Polyline {
+    // Zero-length edges have no direction to move away from
+    let mut points = points.to_vec();
+    points.dedup();
+    let last = points.len().saturating_sub(1);
+    if distance == 0.0 || last == 0 {
+        return points;
+    }
+    if last > 2 && points[0] == points[last] {
+        let mut ring = offset_polygon(&points[..last], distance);
+        ring.push(ring[0]);
+        return ring;
+    }
+
+    (0..=last)
+        .map(|i| {
+            let incoming = left_normal(points[i.max(1) - 1], points[i.max(1)]);
+            let outgoing = left_normal(points[i.min(last - 1)], points[i.min(last - 1) + 1]);
+            miter_point(points[i], incoming, outgoing, distance)
+        })
+        .collect()
+}
+
+/// Where a corner with edge normals `n1` and `n2` moves to when its edges move `distance`
+fn miter_point(point: (f32, f32), n1: (f32, f32), n2: (f32, f32), distance: f32) -> (f32, f32) {
+    let (bx, by) = (n1.0 + n2.0, n1.1 + n2.1);
+    let length = (bx * bx + by * by).sqrt();
+    if length < f32::EPSILON {
+        return (point.0 + n1.0 * distance, point.1 + n1.1 * distance);
+    }
+    let (bx, by) = (bx / length, by / length);
+    // Stretch the move at sharp corners so the edges end up `distance` away, within reason
+    let miter = distance / (bx * n1.0 + by * n1.1).max(0.3);
+    (point.0 + bx * miter, point.1 + by * miter)
+}
+
+/// Keep the parts of a polyline that are on the canvas, lifting the pen where it goes off
+fn clip_to_canvas(polyline: Polyline, width: f32, height: f32) -> Vec<Polyline> {
+    let (max_x, max_y) = (width - 1.0, height - 1.0);
+    if let [point] = polyline[..] {
+        let on_canvas = (0.0..=max_x).contains(&point.0) && (0.0..=max_y).contains(&point.1);
+        return if on_canvas { vec![polyline] } else { Vec::new() };
+    }
+
+    let mut pieces: Vec<Polyline> = Vec::new();
+    let mut current: Polyline = Vec::new();
+    for pair in polyline.windows(2) {
+        let Some((start, end)) = clip_segment(pair[0], pair[1], max_x, max_y) else {
+            continue;
+        };
+        if current.last() != Some(&start) {
+            if current.len() > 1 {
+                pieces.push(std::mem::take(&mut current));
+            }
+            current = vec![start];
+        }
+        current.push(end);
+    }
+    if current.len() > 1 {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// The part of the segment inside 0..=max_x, 0..=max_y (Liang-Barsky), if any
+fn clip_segment(a: (f32, f32), b: (f32, f32), max_x: f32, max_y: f32) -> Option<((f32, f32), (f32, f32))> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut enter, mut exit) = (0.0f32, 1.0f32);
+    for (p, q) in [(-dx, a.0), (dx, max_x - a.0), (-dy, a.1), (dy, max_y - a.1)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                enter = enter.max(t);
+            } else {
+                exit = exit.min(t);
+            }
+        }
+    }
+    if enter > exit {
+        return None;
+    }
+    let at = |t: f32| {
+        if t == 0.0 {
+            a
+        } else if t == 1.0 {
+            b
+        } else {
+            (a.0 + dx * t, a.1 + dy * t)
+        }
+    };
+    Some((at(enter), at(exit)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_HATCH: Hatch = Hatch {
+        style: HatchStyle::None,
+        spacing: 10.0,
+    };
+
+    fn strokes_of(body: &str, hatch: Hatch) -> Vec<Polyline> {
+        let svg = format!(r#"<svg width="768" height="1024" xmlns="http://www.w3.org/2000/svg">{}</svg>"#, body);
+        svg_to_strokes(&svg, 768, 1024, hatch)
+            .unwrap()
+            .into_iter()
+            .map(|stroke| stroke.points)
+            .collect()
+    }
+
+    #[test]
+    fn straight_path_is_one_stroke_through_its_points() {
+        let strokes = strokes_of(r#"<path d="M10 10 L100 10 L100 50" stroke="black" fill="none"/>"#, NO_HATCH);
+        assert_eq!(strokes, vec![vec![(10.0, 10.0), (100.0, 10.0), (100.0, 50.0)]]);
+    }
+
+    #[test]
+    fn each_subpath_is_its_own_stroke() {
+        let strokes = strokes_of(r#"<path d="M10 10 L50 10 M10 30 L50 30" stroke="black" fill="none"/>"#, NO_HATCH);
+        assert_eq!(strokes, vec![vec![(10.0, 10.0), (50.0, 10.0)], vec![(10.0, 30.0), (50.0, 30.0)]]);
+    }
+
+    #[test]
+    fn transforms_are_applied() {
+        let strokes = strokes_of(
+            r#"<g transform="translate(100 200) scale(2)"><path d="M0 0 L10 5" stroke="black" fill="none"/></g>"#,
+            NO_HATCH,
+        );
+        assert_eq!(strokes, vec![vec![(100.0, 200.0), (120.0, 210.0)]]);
+    }
+
+    #[test]
+    fn curves_flatten_within_tolerance() {
+        // Peaks at (150, 175), three quarters of the way to the control points
+        let strokes = strokes_of(r#"<path d="M100 100 C100 200 200 200 200 100" stroke="black" fill="none"/>"#, NO_HATCH);
+        assert_eq!(strokes.len(), 1);
+        let points = &strokes[0];
+        assert!(points.len() > 8, "only {} points", points.len());
+        assert_eq!(points.first(), Some(&(100.0, 100.0)));
+        assert_eq!(points.last(), Some(&(200.0, 100.0)));
+        let lowest = points.iter().map(|point| point.1).fold(f32::MIN, f32::max);
+        assert!((lowest - 175.0).abs() <= FLATNESS_TOLERANCE, "lowest point {}", lowest);
+    }
+
+    #[test]
+    fn invisible_and_white_paths_are_skipped() {
+        let strokes = strokes_of(
+            r#"<path d="M10 10 L50 10" stroke="white" fill="none"/><path d="M10 30 L50 30" stroke="none" fill="none"/>"#,
+            NO_HATCH,
+        );
+        assert!(strokes.is_empty());
+    }
+
+    #[test]
+    fn strokes_are_clipped_to_the_canvas() {
+        assert_eq!(
+            clip_to_canvas(vec![(-50.0, 10.0), (50.0, 10.0)], 768.0, 1024.0),
+            vec![vec![(0.0, 10.0), (50.0, 10.0)]]
+        );
+        // Off the right edge and back on again is two strokes
+        let pieces = clip_to_canvas(vec![(700.0, 10.0), (800.0, 10.0), (800.0, 50.0), (700.0, 50.0)], 768.0, 1024.0);
+        assert_eq!(pieces, vec![vec![(700.0, 10.0), (767.0, 10.0)], vec![(767.0, 50.0), (700.0, 50.0)]]);
+        assert!(clip_to_canvas(vec![(-10.0, -10.0), (-5.0, 2000.0)], 768.0, 1024.0).is_empty());
+    }
+
+    fn square(x: f32, y: f32, side: f32) -> Polyline {
+        vec![(x, y), (x + side, y), (x + side, y + side), (x, y + side), (x, y)]
+    }
+
+    fn is_within(point: (f32, f32), x: f32, y: f32, side: f32) -> bool {
+        let tolerance = 1e-3;
+        (x - tolerance..=x + side + tolerance).contains(&point.0) && (y - tolerance..=y + side + tolerance).contains(&point.1)
+    }
+
+    #[test]
+    fn filled_square_hatches_to_diagonal_lines_inside_it() {
+        // The 100px square spans 141px across the diagonal, so lines every 10px give 15
+        let lines = hatch_lines(&[square(100.0, 100.0, 100.0)], FillRule::NonZero, HATCH_ANGLE, 10.0);
+        assert_eq!(lines.len(), 15);
+        for line in &lines {
+            assert_eq!(line.len(), 2);
+            assert!(line.iter().all(|&point| is_within(point, 100.0, 100.0, 100.0)), "{:?} leaves the square", line);
+            let (dx, dy) = (line[1].0 - line[0].0, line[1].1 - line[0].1);
+            assert!((dx.abs() - dy.abs()).abs() < 1e-3, "{:?} isn't at 45 degrees", line);
+        }
+        // Zigzag: every line runs the other way from the one before
+        for pair in lines.windows(2) {
+            let direction = |line: &Polyline| (line[1].0 - line[0].0, line[1].1 - line[0].1);
+            let ((ax, ay), (bx, by)) = (direction(&pair[0]), direction(&pair[1]));
+            assert!(ax * bx + ay * by < 0.0);
+        }
+    }
+
+    #[test]
+    fn hatching_follows_fill_darkness_and_style() {
+        let black = r#"<rect x="100" y="100" width="100" height="100" fill="black"/>"#;
+        let gray = r##"<rect x="100" y="100" width="100" height="100" fill="#808080"/>"##;
+        let hatch = |style| Hatch { style, spacing: 10.0 };
+        // Hatch lines plus the outline
+        assert_eq!(strokes_of(black, hatch(HatchStyle::None)).len(), 1);
+        assert_eq!(strokes_of(black, hatch(HatchStyle::Parallel)).len(), 16);
+        // A mid gray spaces the lines about twice as far apart
+        assert_eq!(strokes_of(gray, hatch(HatchStyle::Parallel)).len(), 8);
+        // Dark fills get hatched the other way too, mid grays don't
+        assert_eq!(strokes_of(black, hatch(HatchStyle::Cross)).len(), 30);
+        assert_eq!(strokes_of(gray, hatch(HatchStyle::Cross)).len(), 8);
+    }
+
+    #[test]
+    fn hatching_skips_holes() {
+        let outlines = [square(100.0, 100.0, 100.0), square(130.0, 130.0, 40.0)];
+        for fill_rule in [FillRule::EvenOdd, FillRule::NonZero] {
+            let mut outlines = outlines.clone();
+            if fill_rule == FillRule::NonZero {
+                // Non-zero only leaves a hole where the inner outline winds the other way
+                outlines[1].reverse();
+            }
+            let lines = hatch_lines(&outlines, fill_rule, HATCH_ANGLE, 10.0);
+            assert!(lines.len() > 15, "lines through the hole should be split in two");
+            for line in &lines {
+                let middle = ((line[0].0 + line[1].0) / 2.0, (line[0].1 + line[1].1) / 2.0);
+                assert!(!is_within(middle, 131.0, 131.0, 38.0), "{:?} crosses the hole", line);
+            }
+        }
+    }
+
+    #[test]
+    fn contour_hatching_terminates() {
+        // Rings every 10px until they meet in the middle of the 100px square
+        let rings = contour_lines(&[square(100.0, 100.0, 100.0)], FillRule::NonZero, 10.0);
+        assert_eq!(rings.len(), 4);
+        assert!(rings.iter().flatten().all(|&point| is_within(point, 110.0, 110.0, 80.0)));
+
+        // Fine spacing on a big shape still stops when the rings close up
+        let rings = contour_lines(&[square(0.0, 0.0, 300.0)], FillRule::NonZero, 1.0);
+        assert!(!rings.is_empty() && rings.len() < MAX_CONTOUR_RINGS);
+
+        // Shapes without an inside to shrink into
+        let bowtie = vec![(0.0, 0.0), (100.0, 100.0), (100.0, 0.0), (0.0, 100.0), (0.0, 0.0)];
+        assert!(contour_lines(&[bowtie], FillRule::NonZero, 10.0).len() < MAX_CONTOUR_RINGS);
+        let flat = vec![(0.0, 0.0), (50.0, 0.0), (100.0, 0.0), (0.0, 0.0)];
+        assert!(contour_lines(&[flat], FillRule::NonZero, 10.0).is_empty());
+        assert!(contour_lines(&[square(0.0, 0.0, 5.0)], FillRule::NonZero, 10.0).is_empty());
+    }
+}
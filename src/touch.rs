@@ -1,12 +1,24 @@
 use anyhow::Result;
 use evdev::EventType as EvdevEventType;
 use evdev::{Device, InputEvent};
-use log::{debug, info, trace};
+use log::{debug, error, info, trace};
+use serde::{Deserialize, Serialize};
 
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::device::DeviceModel;
+use crate::cancel::CancelToken;
+use crate::device::{find_input_device, DeviceModel, InputDeviceKind};
+use crate::gesture::{read_evtest_log, Gesture, GestureAction, GestureBinding, GestureKind, GestureRecognizer, TouchEvent};
+use crate::pen_input::PenProximity;
+use crate::trace::{TraceDevice, TraceRecorder};
+
+// Touches this soon after the pen leaves the screen are still the hand that held it
+const PEN_PROXIMITY_GRACE: Duration = Duration::from_millis(500);
+// How often to check whether a tap held back for a possible double-tap can be let go
+const PENDING_TAP_POLL: Duration = Duration::from_millis(20);
 
 #[derive(Debug, Clone)]
 pub enum TriggerCorner {
@@ -29,6 +41,92 @@ impl TriggerCorner {
             )),
         }
     }
+
+    pub fn name(&self) -> &str {
+        match self {
+            TriggerCorner::UpperRight => "upper-right",
+            TriggerCorner::UpperLeft => "upper-left",
+            TriggerCorner::LowerRight => "lower-right",
+            TriggerCorner::LowerLeft => "lower-left",
+        }
+    }
+}
+
+// Default size of a corner trigger zone (68x68 pixels)
+const CORNER_SIZE: i32 = 68;
+
+/// A region of the screen that triggers ghostwriter when tapped, optionally with its own prompt.
+/// A zone is either a `corner` (with an optional `size`) or an arbitrary `rect`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TriggerZone {
+    pub name: Option<String>,
+    pub corner: Option<String>,
+    pub size: Option<i32>,
+    /// [x, y, width, height] in virtual coordinates
+    pub rect: Option<[i32; 4]>,
+    pub prompt: Option<String>,
+}
+
+impl TriggerZone {
+    pub fn from_corner(corner: &str) -> Self {
+        Self {
+            corner: Some(corner.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match (&self.corner, &self.rect) {
+            (Some(corner), None) => {
+                TriggerCorner::from_string(corner)?;
+            }
+            (None, Some([_, _, width, height])) => {
+                if *width <= 0 || *height <= 0 {
+                    return Err(anyhow::anyhow!("Trigger zone {} has an empty rect", self.name()));
+                }
+            }
+            _ => return Err(anyhow::anyhow!("Trigger zone {} needs exactly one of 'corner' or 'rect'", self.name())),
+        }
+        if self.size.is_some_and(|size| size <= 0) {
+            return Err(anyhow::anyhow!("Trigger zone {} size must be greater than 0", self.name()));
+        }
+        Ok(())
+    }
+
+    /// The configured name, or a description of where the zone is
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        if let Some(corner) = self.corner.as_deref().and_then(|corner| TriggerCorner::from_string(corner).ok()) {
+            return corner.name().to_string();
+        }
+        match self.rect {
+            Some([x, y, width, height]) => format!("{}x{}+{}+{}", width, height, x, y),
+            None => "unknown".to_string(),
+        }
+    }
+
+    /// The zone as an (x, y, width, height) rectangle in virtual coordinates
+    pub fn bounds(&self) -> Option<(i32, i32, i32, i32)> {
+        if let Some([x, y, width, height]) = self.rect {
+            return Some((x, y, width, height));
+        }
+        let size = self.size.unwrap_or(CORNER_SIZE);
+        let right = VIRTUAL_WIDTH as i32 - size;
+        let bottom = VIRTUAL_HEIGHT as i32 - size;
+        match TriggerCorner::from_string(self.corner.as_deref()?).ok()? {
+            TriggerCorner::UpperRight => Some((right, 0, size, size)),
+            TriggerCorner::UpperLeft => Some((0, 0, size, size)),
+            TriggerCorner::LowerRight => Some((right, bottom, size, size)),
+            TriggerCorner::LowerLeft => Some((0, bottom, size, size)),
+        }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.bounds()
+            .is_some_and(|(zone_x, zone_y, width, height)| x >= zone_x && x < zone_x + width && y >= zone_y && y < zone_y + height)
+    }
 }
 
 // Output dimensions remain the same for both devices
@@ -46,70 +144,188 @@ const ABS_MT_POSITION_Y: u16 = 54;
 const ABS_MT_TRACKING_ID: u16 = 57;
 const ABS_MT_PRESSURE: u16 = 58;
 
+/// What the user asked for while ghostwriter was waiting for a trigger
+#[derive(Debug, Clone)]
+pub enum TouchAction {
+    Trigger(TriggerZone),
+    Cancel,
+    Undo,
+    SwitchPrompt(String),
+}
+
 pub struct Touch {
     device: Option<Device>,
     device_model: DeviceModel,
-    trigger_corner: TriggerCorner,
+    trigger_zones: Vec<TriggerZone>,
+    gesture_bindings: Vec<GestureBinding>,
+    // Where the listener thread reads touches from, unless there is a replay log
+    device_path: Option<String>,
+    // Recorded events to read instead of the device, see `replay_log`
+    replay: Option<VecDeque<TouchEvent>>,
+    // Gestures from the listener thread, see `listen`
+    gestures: Option<Receiver<Gesture>>,
+    cancel: CancelToken,
+    // Palm rejection, see `reject_palms`
+    max_touch_major: Option<i32>,
+    pen_proximity: Option<PenProximity>,
+    recorder: Option<TraceRecorder>,
 }
 
 impl Touch {
-    pub fn new(no_touch: bool, trigger_corner: TriggerCorner) -> Self {
+    /// Open the touchscreen, or the device at `touch_device` if given, unless `no_touch`
+    pub fn new(no_touch: bool, touch_device: Option<&str>, trigger_zones: Vec<TriggerZone>, gesture_bindings: Vec<GestureBinding>) -> Result<Self> {
         let device_model = DeviceModel::detect();
         info!("Touch using device model: {}", device_model.name());
 
-        let device_path = match device_model {
-            DeviceModel::Remarkable2 => "/dev/input/event2",
-            DeviceModel::RemarkablePaperPro => "/dev/input/event3",
-            DeviceModel::Unknown => "/dev/input/event2", // Default to RM2
+        let (device, device_path) = if no_touch {
+            (None, None)
+        } else {
+            let path = find_input_device(InputDeviceKind::Touch, touch_device)?;
+            let device = Device::open(&path).map_err(|e| anyhow::anyhow!("Failed to open touch device {}: {}", path, e))?;
+            (Some(device), Some(path))
         };
 
-        let device = if no_touch { None } else { Some(Device::open(device_path).unwrap()) };
-
-        Self {
+        Ok(Self {
             device,
             device_model,
-            trigger_corner,
+            trigger_zones,
+            gesture_bindings,
+            device_path,
+            replay: None,
+            gestures: None,
+            cancel: CancelToken::new(),
+            max_touch_major: None,
+            pen_proximity: None,
+            recorder: None,
+        })
+    }
+
+    /// Record every event the touchscreen writer sends, even with no device open
+    pub fn set_recorder(&mut self, recorder: TraceRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Ignore contacts larger than `max_touch_major` (raw device units), and every touch while the
+    /// pen is near the screen. Takes effect when `listen` starts the listener.
+    pub fn reject_palms(&mut self, max_touch_major: Option<i32>, pen_proximity: Option<PenProximity>) {
+        self.max_touch_major = max_touch_major;
+        self.pen_proximity = pen_proximity;
+    }
+
+    /// Read touches from an `evtest` log instead of the touchscreen
+    pub fn replay_log(&mut self, path: &str) -> Result<()> {
+        info!("Replaying touch events from {}", path);
+        self.replay = Some(read_evtest_log(path)?);
+        Ok(())
+    }
+
+    /// Start reading touches on a background thread, so gestures are seen even while we're busy.
+    /// A gesture bound to `cancel` cancels the token right away, whatever the main thread is doing.
+    pub fn listen(&mut self, cancel: CancelToken) -> Result<()> {
+        self.cancel = cancel.clone();
+        let source = match (self.replay.take(), &self.device_path) {
+            (Some(replay), _) => TouchSource::Replay(replay),
+            (None, Some(device_path)) => TouchSource::Device(Box::new(
+                Device::open(device_path).map_err(|e| anyhow::anyhow!("Failed to open touch device {} for reading: {}", device_path, e))?,
+            )),
+            (None, None) => {
+                debug!("No touch device, not listening for gestures");
+                return Ok(());
+            }
+        };
+        let cancel_gestures: Vec<GestureKind> = self
+            .gesture_bindings
+            .iter()
+            .filter(|binding| binding.action == GestureAction::Cancel)
+            .map(|binding| binding.gesture)
+            .collect();
+        let (sender, receiver) = channel();
+        let mut recognizer = GestureRecognizer::new(self.device_model);
+        recognizer.set_max_touch_major(self.max_touch_major);
+        let pen_proximity = self.pen_proximity.clone();
+        std::thread::spawn(move || listen_for_gestures(source, recognizer, pen_proximity, sender, cancel_gestures, cancel));
+        self.gestures = Some(receiver);
+        Ok(())
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.gestures.is_some()
+    }
+
+    /// Drop touches made while we were busy (including our own taps), so they don't trigger again
+    pub fn discard_pending(&self) {
+        if let Some(gestures) = &self.gestures {
+            let stale = gestures.try_iter().count();
+            if stale > 0 {
+                debug!("Ignoring {} gesture(s) from while we were busy", stale);
+            }
         }
     }
 
-    pub fn wait_for_trigger(&mut self) -> Result<()> {
-        let mut position_x = 0;
-        let mut position_y = 0;
-        loop {
-            // Store events in a temporary vector to avoid borrowing issues
-            let mut events_to_process = Vec::new();
-            if let Some(device) = &mut self.device {
-                for event in device.fetch_events()? {
-                    events_to_process.push(event);
-                }
+    /// Wait up to `timeout` for a tap in a trigger zone or a bound gesture and return what it asks for
+    pub fn poll_trigger(&mut self, timeout: Duration) -> Result<Option<TouchAction>> {
+        let Some(gestures) = &self.gestures else {
+            sleep(timeout);
+            return Ok(None);
+        };
+        let gesture = match gestures.recv_timeout(timeout) {
+            Ok(gesture) => gesture,
+            Err(RecvTimeoutError::Timeout) => return Ok(None),
+            Err(RecvTimeoutError::Disconnected) => return Err(anyhow::anyhow!("Touch listener stopped")),
+        };
+
+        if gesture.kind == GestureKind::Tap {
+            if let Some(zone) = self.trigger_zones.iter().find(|zone| zone.contains(gesture.x, gesture.y)) {
+                debug!("Tap in trigger zone {}!", zone.name());
+                return Ok(Some(TouchAction::Trigger(zone.clone())));
             }
+        }
+        let Some(binding) = self.gesture_bindings.iter().find(|binding| binding.gesture == gesture.kind) else {
+            return Ok(None);
+        };
+        info!("Gesture {} bound to {:?}", gesture.kind.name(), binding.action);
+        Ok(Some(match binding.action {
+            GestureAction::Trigger => TouchAction::Trigger(TriggerZone {
+                name: Some(gesture.kind.name().to_string()),
+                prompt: binding.prompt.clone(),
+                ..Default::default()
+            }),
+            GestureAction::Cancel => TouchAction::Cancel,
+            GestureAction::Undo => TouchAction::Undo,
+            GestureAction::SwitchPrompt => TouchAction::SwitchPrompt(binding.prompt.clone().unwrap_or_default()),
+        }))
+    }
 
-            // Process the events after releasing the mutable borrow
-            for event in events_to_process {
-                if event.code() == ABS_MT_POSITION_X {
-                    position_x = event.value();
-                }
-                if event.code() == ABS_MT_POSITION_Y {
-                    position_y = event.value();
-                }
-                if event.code() == ABS_MT_TRACKING_ID && event.value() == -1 {
-                    let (x, y) = self.input_to_virtual((position_x, position_y));
-                    debug!("Touch release detected at ({}, {}) normalized ({}, {})", position_x, position_y, x, y);
-                    if self.is_in_trigger_zone(x, y) {
-                        debug!("Touch release in target zone!");
-                        return Ok(());
-                    }
-                }
+    /// Wait for a tap inside one of the given (x, y, width, height) boxes and return its index
+    pub fn wait_for_tap_in(&mut self, boxes: &[(i32, i32, i32, i32)]) -> Result<usize> {
+        let gestures = self
+            .gestures
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No touch device available to wait for a tap"))?;
+        loop {
+            self.cancel.check()?;
+            let gesture = match gestures.recv_timeout(Duration::from_millis(100)) {
+                Ok(gesture) => gesture,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Err(anyhow::anyhow!("Touch listener stopped")),
+            };
+            if gesture.kind != GestureKind::Tap {
+                continue;
+            }
+            let (x, y) = (gesture.x, gesture.y);
+            if let Some(index) = boxes.iter().position(|&(bx, by, bw, bh)| x >= bx && x < bx + bw && y >= by && y < by + bh) {
+                debug!("Tap in box {}", index);
+                return Ok(index);
             }
         }
     }
 
     pub fn touch_start(&mut self, xy: (i32, i32)) -> Result<()> {
         let (x, y) = self.virtual_to_input(xy);
-        if let Some(device) = &mut self.device {
-            trace!("touch_start at ({}, {})", x, y);
-            // sleep(Duration::from_millis(100));
-            device.send_events(&[
+        trace!("touch_start at ({}, {})", x, y);
+        // sleep(Duration::from_millis(100));
+        self.send(
+            &[
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_SLOT, 0),
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_TRACKING_ID, 1),
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_POSITION_X, x),
@@ -119,35 +335,48 @@ impl Touch {
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_TOUCH_MINOR, 17),
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_ORIENTATION, 4),
                 InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0), // SYN_REPORT
-            ])?;
-            sleep(Duration::from_millis(1));
-        }
-        Ok(())
+            ],
+            Duration::from_millis(1),
+        )
     }
 
     pub fn touch_stop(&mut self) -> Result<()> {
-        if let Some(device) = &mut self.device {
-            trace!("touch_stop");
-            device.send_events(&[
+        trace!("touch_stop");
+        self.send(
+            &[
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_SLOT, 0),
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_TRACKING_ID, -1),
                 InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0), // SYN_REPORT
-            ])?;
-            sleep(Duration::from_millis(1));
-        }
-        Ok(())
+            ],
+            Duration::from_millis(1),
+        )
     }
 
     pub fn goto_xy(&mut self, xy: (i32, i32)) -> Result<()> {
         let (x, y) = self.virtual_to_input(xy);
-        if let Some(device) = &mut self.device {
-            device.send_events(&[
+        self.send(
+            &[
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_SLOT, 0),
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_TRACKING_ID, 1),
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_POSITION_X, x),
                 InputEvent::new(EvdevEventType::ABSOLUTE.0, ABS_MT_POSITION_Y, y),
                 InputEvent::new(EvdevEventType::SYNCHRONIZATION.0, 0, 0), // SYN_REPORT
-            ])?;
+            ],
+            Duration::ZERO,
+        )
+    }
+
+    /// Write events to the touchscreen, then pause for `pause_after`, and add them to the trace
+    /// if one is being recorded
+    fn send(&mut self, events: &[InputEvent], pause_after: Duration) -> Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(TraceDevice::Touch, events)?;
+        }
+        if let Some(device) = &mut self.device {
+            device.send_events(events)?;
+            if !pause_after.is_zero() {
+                sleep(pause_after);
+            }
         }
         Ok(())
     }
@@ -161,33 +390,6 @@ impl Touch {
         Ok(())
     }
 
-    fn is_in_trigger_zone(&self, x: i32, y: i32) -> bool {
-        const CORNER_SIZE: i32 = 68; // Size of the trigger zone (68x68 pixels)
-
-        match self.trigger_corner {
-            TriggerCorner::UpperRight => x > VIRTUAL_WIDTH as i32 - CORNER_SIZE && y < CORNER_SIZE,
-            TriggerCorner::UpperLeft => x < CORNER_SIZE && y < CORNER_SIZE,
-            TriggerCorner::LowerRight => x > VIRTUAL_WIDTH as i32 - CORNER_SIZE && y > VIRTUAL_HEIGHT as i32 - CORNER_SIZE,
-            TriggerCorner::LowerLeft => x < CORNER_SIZE && y > VIRTUAL_HEIGHT as i32 - CORNER_SIZE,
-        }
-    }
-
-    fn screen_width(&self) -> u32 {
-        match self.device_model {
-            DeviceModel::Remarkable2 => 1404,
-            DeviceModel::RemarkablePaperPro => 2065,
-            DeviceModel::Unknown => 1404, // Default to RM2
-        }
-    }
-
-    fn screen_height(&self) -> u32 {
-        match self.device_model {
-            DeviceModel::Remarkable2 => 1872,
-            DeviceModel::RemarkablePaperPro => 2833,
-            DeviceModel::Unknown => 1872, // Default to RM2
-        }
-    }
-
     fn virtual_to_input(&self, (x, y): (i32, i32)) -> (i32, i32) {
         // Swap and normalize the coordinates
         let x_normalized = x as f32 / VIRTUAL_WIDTH as f32;
@@ -195,36 +397,153 @@ impl Touch {
 
         match self.device_model {
             DeviceModel::RemarkablePaperPro => {
-                let x_input = (x_normalized * self.screen_width() as f32) as i32;
-                let y_input = (y_normalized * self.screen_height() as f32) as i32;
+                let x_input = (x_normalized * screen_width(self.device_model) as f32) as i32;
+                let y_input = (y_normalized * screen_height(self.device_model) as f32) as i32;
                 (x_input, y_input)
             }
             _ => {
                 // RM2 coordinate transformation
-                let x_input = (x_normalized * self.screen_width() as f32) as i32;
-                let y_input = ((1.0 - y_normalized) * self.screen_height() as f32) as i32;
+                let x_input = (x_normalized * screen_width(self.device_model) as f32) as i32;
+                let y_input = ((1.0 - y_normalized) * screen_height(self.device_model) as f32) as i32;
                 (x_input, y_input)
             }
         }
     }
+}
 
-    fn input_to_virtual(&self, (x, y): (i32, i32)) -> (i32, i32) {
-        // Swap and normalize the coordinates
-        let x_normalized = x as f32 / self.screen_width() as f32;
-        let y_normalized = y as f32 / self.screen_height() as f32;
+enum TouchSource {
+    Device(Box<Device>),
+    Replay(VecDeque<TouchEvent>),
+}
 
-        match self.device_model {
-            DeviceModel::RemarkablePaperPro => {
-                let x_input = (x_normalized * VIRTUAL_WIDTH as f32) as i32;
-                let y_input = (y_normalized * VIRTUAL_HEIGHT as f32) as i32;
-                (x_input, y_input)
+/// Body of the listener thread: turn events into gestures and hand them to `Touch`
+fn listen_for_gestures(
+    mut source: TouchSource,
+    mut recognizer: GestureRecognizer,
+    pen_proximity: Option<PenProximity>,
+    sender: Sender<Gesture>,
+    cancel_gestures: Vec<GestureKind>,
+    cancel: CancelToken,
+) {
+    let mut last_replayed: Option<f64> = None;
+    // Set when the pen came near during the current gesture
+    let mut pen_was_near = false;
+    let send = |gesture: Gesture| {
+        if cancel_gestures.contains(&gesture.kind) {
+            info!("Abort gesture {} detected, cancelling", gesture.kind.name());
+            cancel.cancel();
+        }
+        sender.send(gesture).is_ok()
+    };
+    loop {
+        let events: Vec<TouchEvent> = match &mut source {
+            TouchSource::Device(device) => {
+                // While a tap waits to see if it becomes a double-tap, don't block on the device,
+                // so the tap can be let go as soon as the wait is over
+                if let Err(e) = device.set_nonblocking(recognizer.has_pending_tap()) {
+                    error!("Failed to set up reading touch events: {}", e);
+                    return;
+                }
+                match device.fetch_events() {
+                    Ok(events) => events.map(|event| TouchEvent::from_input_event(&event)).collect(),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        sleep(PENDING_TAP_POLL);
+                        Vec::new()
+                    }
+                    Err(e) => {
+                        error!("Failed to read touch events: {}", e);
+                        return;
+                    }
+                }
             }
-            _ => {
-                // RM2 coordinate transformation
-                let x_input = (x_normalized * VIRTUAL_WIDTH as f32) as i32;
-                let y_input = ((1.0 - y_normalized) * VIRTUAL_HEIGHT as f32) as i32;
-                (x_input, y_input)
+            TouchSource::Replay(replay) => {
+                // Play the log back at the speed it was recorded
+                let Some(event) = replay.pop_front() else {
+                    if let Some(tap) = recognizer.expire_pending(f64::INFINITY) {
+                        send(tap);
+                    }
+                    info!("Touch replay log finished");
+                    return;
+                };
+                if let Some(last_time) = last_replayed {
+                    sleep(Duration::from_secs_f64((event.time - last_time).max(0.0)));
+                }
+                last_replayed = Some(event.time);
+                vec![event]
             }
+        };
+
+        if events.is_empty() {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+            if let Some(tap) = recognizer.expire_pending(now) {
+                if !send(tap) {
+                    return;
+                }
+            }
+        }
+
+        for event in events {
+            if let Some(tap) = recognizer.expire_pending(event.time) {
+                if !send(tap) {
+                    return;
+                }
+            }
+            pen_was_near |= pen_proximity.as_ref().is_some_and(|pen| pen.near_within(PEN_PROXIMITY_GRACE));
+            let gesture = recognizer.process(&event);
+            let suppressed = pen_was_near;
+            if recognizer.is_idle() {
+                if suppressed {
+                    // Including a tap it would hold back
+                    recognizer.discard_pending();
+                }
+                pen_was_near = false;
+            }
+            if let Some(gesture) = gesture {
+                if suppressed {
+                    debug!("Ignoring {} while the pen is near the screen", gesture.kind.name());
+                    continue;
+                }
+                if !send(gesture) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn screen_width(device_model: DeviceModel) -> u32 {
+    match device_model {
+        DeviceModel::Remarkable2 => 1404,
+        DeviceModel::RemarkablePaperPro => 2065,
+        DeviceModel::Unknown => 1404, // Default to RM2
+    }
+}
+
+fn screen_height(device_model: DeviceModel) -> u32 {
+    match device_model {
+        DeviceModel::Remarkable2 => 1872,
+        DeviceModel::RemarkablePaperPro => 2833,
+        DeviceModel::Unknown => 1872, // Default to RM2
+    }
+}
+
+/// Map a touchscreen position to virtual coordinates
+pub fn input_to_virtual(device_model: DeviceModel, (x, y): (i32, i32)) -> (i32, i32) {
+    // Swap and normalize the coordinates
+    let x_normalized = x as f32 / screen_width(device_model) as f32;
+    let y_normalized = y as f32 / screen_height(device_model) as f32;
+
+    match device_model {
+        DeviceModel::RemarkablePaperPro => {
+            let x_input = (x_normalized * VIRTUAL_WIDTH as f32) as i32;
+            let y_input = (y_normalized * VIRTUAL_HEIGHT as f32) as i32;
+            (x_input, y_input)
+        }
+        _ => {
+            // RM2 coordinate transformation
+            let x_input = (x_normalized * VIRTUAL_WIDTH as f32) as i32;
+            let y_input = ((1.0 - y_normalized) * VIRTUAL_HEIGHT as f32) as i32;
+            (x_input, y_input)
         }
     }
 }
@@ -0,0 +1,292 @@
+use anyhow::Result;
+use evdev::{Device, InputEvent};
+use log::{debug, info, warn};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::device::{find_input_device, InputDeviceKind};
+
+// First line of every trace file; lines starting with '#' are comments
+const TRACE_HEADER: &str = "# ghostwriter trace v1: seconds device type code value";
+
+/// Which of our input writers sent an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceDevice {
+    Pen,
+    Touch,
+}
+
+impl TraceDevice {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceDevice::Pen => "pen",
+            TraceDevice::Touch => "touch",
+        }
+    }
+
+    pub fn from_string(s: &str) -> Result<Self> {
+        match s {
+            "pen" => Ok(TraceDevice::Pen),
+            "touch" => Ok(TraceDevice::Touch),
+            _ => Err(anyhow::anyhow!("Invalid trace device: {}. Use pen or touch", s)),
+        }
+    }
+
+    fn input_kind(&self) -> InputDeviceKind {
+        match self {
+            TraceDevice::Pen => InputDeviceKind::Pen,
+            TraceDevice::Touch => InputDeviceKind::Touch,
+        }
+    }
+}
+
+/// One evdev event ghostwriter sent, `time` after the recording started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub time: Duration,
+    pub device: TraceDevice,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl TraceEvent {
+    /// Parse a line like `1.250000 pen 3 24 2630`
+    pub fn parse(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [time, device, event_type, code, value] = fields[..] else {
+            return Err(anyhow::anyhow!("Expected 5 fields, found {}", fields.len()));
+        };
+        let seconds: f64 = time.parse().map_err(|_| anyhow::anyhow!("Invalid time '{}'", time))?;
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(anyhow::anyhow!("Invalid time '{}'", time));
+        }
+        Ok(Self {
+            time: Duration::from_secs_f64(seconds),
+            device: TraceDevice::from_string(device)?,
+            event_type: event_type.parse().map_err(|_| anyhow::anyhow!("Invalid event type '{}'", event_type))?,
+            code: code.parse().map_err(|_| anyhow::anyhow!("Invalid event code '{}'", code))?,
+            value: value.parse().map_err(|_| anyhow::anyhow!("Invalid event value '{}'", value))?,
+        })
+    }
+
+    fn input_event(&self) -> InputEvent {
+        InputEvent::new(self.event_type, self.code, self.value)
+    }
+
+    /// The end of a batch of events, SYN_REPORT
+    fn is_sync(&self) -> bool {
+        self.event_type == 0 && self.code == 0
+    }
+}
+
+impl std::fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.6} {} {} {} {}",
+            self.time.as_secs_f64(),
+            self.device.name(),
+            self.event_type,
+            self.code,
+            self.value
+        )
+    }
+}
+
+/// Writes every event the pen and touchscreen writers send to a trace file, with one clock for
+/// both. Clones share the same file.
+#[derive(Clone)]
+pub struct TraceRecorder {
+    writer: Arc<Mutex<BufWriter<File>>>,
+    started: Instant,
+}
+
+impl TraceRecorder {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).map_err(|e| anyhow::anyhow!("Failed to create trace file '{}': {}", path, e))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "{}", TRACE_HEADER)?;
+        writer.flush()?;
+        info!("Recording pen and touch events to {}", path);
+        Ok(Self {
+            writer: Arc::new(Mutex::new(writer)),
+            started: Instant::now(),
+        })
+    }
+
+    /// Record a batch of events sent together by `device`
+    pub fn record(&self, device: TraceDevice, events: &[InputEvent]) -> Result<()> {
+        let time = self.started.elapsed();
+        let mut writer = self.writer.lock().unwrap();
+        for event in events {
+            let event = TraceEvent {
+                time,
+                device,
+                event_type: event.event_type().0,
+                code: event.code(),
+                value: event.value(),
+            };
+            writeln!(writer, "{}", event)?;
+        }
+        // Flush every batch, so the trace survives ghostwriter being killed mid-drawing
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Read the events in a trace file, in the order they were sent
+pub fn read_trace(path: &str) -> Result<Vec<TraceEvent>> {
+    let file = File::open(path).map_err(|e| anyhow::anyhow!("Failed to open trace file '{}': {}", path, e))?;
+    let mut events = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let event = TraceEvent::parse(line).map_err(|e| anyhow::anyhow!("Bad trace line {}:{}: {}", path, index + 1, e))?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Send the events in a trace to the real pen and touchscreen, with the same timing as when they
+/// were recorded. `pen_device` and `touch_device` override the devices found by looking.
+pub fn replay_trace(path: &str, pen_device: Option<&str>, touch_device: Option<&str>) -> Result<()> {
+    let events = read_trace(path)?;
+    info!("Replaying {} events from {}", events.len(), path);
+
+    let mut pen = None;
+    let mut touch = None;
+    for device in [TraceDevice::Pen, TraceDevice::Touch] {
+        if !events.iter().any(|event| event.device == device) {
+            continue;
+        }
+        let override_path = if device == TraceDevice::Pen { pen_device } else { touch_device };
+        let device_path = find_input_device(device.input_kind(), override_path)?;
+        let opened = Device::open(&device_path).map_err(|e| anyhow::anyhow!("Failed to open {} device {}: {}", device.name(), device_path, e))?;
+        debug!("Replaying {} events to {}", device.name(), device_path);
+        match device {
+            TraceDevice::Pen => pen = Some(opened),
+            TraceDevice::Touch => touch = Some(opened),
+        }
+    }
+
+    let started = Instant::now();
+    let mut batch: Vec<InputEvent> = Vec::new();
+    for (index, event) in events.iter().enumerate() {
+        if batch.is_empty() {
+            if let Some(wait) = event.time.checked_sub(started.elapsed()) {
+                sleep(wait);
+            }
+        }
+        batch.push(event.input_event());
+        let last = index + 1 == events.len();
+        if event.is_sync() || last {
+            if !event.is_sync() {
+                // A trace cut off mid-batch, such as by killing ghostwriter while it recorded
+                warn!("{} ends with {} events and no SYN_REPORT; sending them anyway", path, batch.len());
+            }
+            let device = match event.device {
+                TraceDevice::Pen => pen.as_mut(),
+                TraceDevice::Touch => touch.as_mut(),
+            };
+            if let Some(device) = device {
+                device.send_events(&batch)?;
+            }
+            batch.clear();
+        }
+    }
+    info!("Replayed {} in {:.1}s", path, started.elapsed().as_secs_f32());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use evdev::EventType;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("ghostwriter-trace-{}-{}.trace", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn recorded_events_read_back() {
+        let path = temp_path("round-trip");
+        let recorder = TraceRecorder::create(&path).unwrap();
+        let pen_events = [
+            InputEvent::new(EventType::KEY.0, 320, 1),
+            InputEvent::new(EventType::ABSOLUTE.0, 24, 2630),
+            InputEvent::new(EventType::SYNCHRONIZATION.0, 0, 0),
+        ];
+        let touch_events = [
+            InputEvent::new(EventType::ABSOLUTE.0, 57, -1),
+            InputEvent::new(EventType::SYNCHRONIZATION.0, 0, 0),
+        ];
+        recorder.record(TraceDevice::Pen, &pen_events).unwrap();
+        recorder.clone().record(TraceDevice::Touch, &touch_events).unwrap();
+
+        let events = read_trace(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let sent: Vec<(TraceDevice, u16, u16, i32)> = pen_events
+            .iter()
+            .map(|event| (TraceDevice::Pen, event))
+            .chain(touch_events.iter().map(|event| (TraceDevice::Touch, event)))
+            .map(|(device, event)| (device, event.event_type().0, event.code(), event.value()))
+            .collect();
+        let read: Vec<(TraceDevice, u16, u16, i32)> = events.iter().map(|event| (event.device, event.event_type, event.code, event.value)).collect();
+        assert_eq!(read, sent);
+        assert!(events.windows(2).all(|pair| pair[0].time <= pair[1].time));
+    }
+
+    #[test]
+    fn written_events_parse_to_the_same_events() {
+        let events = [
+            TraceEvent {
+                time: Duration::from_micros(1_250_000),
+                device: TraceDevice::Pen,
+                event_type: 3,
+                code: 24,
+                value: 2630,
+            },
+            TraceEvent {
+                time: Duration::from_micros(2_000_017),
+                device: TraceDevice::Touch,
+                event_type: 3,
+                code: 57,
+                value: -1,
+            },
+        ];
+        let path = temp_path("write-parse");
+        let text: String = events.iter().map(|event| format!("{}\n", event)).collect();
+        std::fs::write(&path, format!("{}\n{}", TRACE_HEADER, text)).unwrap();
+        let read = read_trace(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, events);
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        for line in [
+            "1.0 pen 3 24",
+            "1.0 pen 3 24 5 6",
+            "1.0 stylus 3 24 5",
+            "-1.0 pen 3 24 5",
+            "1.0 pen 3 x 5",
+            "soon pen 3 24 5",
+        ] {
+            assert!(TraceEvent::parse(line).is_err(), "{} parsed", line);
+        }
+
+        let path = temp_path("malformed");
+        std::fs::write(&path, format!("{}\n0.1 pen 0 0 0\n0.2 pen 3 24\n", TRACE_HEADER)).unwrap();
+        let error = read_trace(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains(":3:"), "{}", error);
+    }
+}
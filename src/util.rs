@@ -15,7 +15,8 @@ use crate::embedded_assets::get_uinput_module_data;
 
 pub type OptionMap = HashMap<String, String>;
 
-pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<bool>>> {
+/// Parse an SVG from the model, falling back to an "ERROR!" note if it can't be parsed
+pub fn parse_svg(svg_data: &str, width: u32, height: u32) -> Result<Tree> {
     let mut opt = Options::default();
     let mut fontdb = fontdb::Database::new();
     fontdb.load_system_fonts();
@@ -32,13 +33,28 @@ pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<
             Tree::from_str(&fallback_svg, &opt)?
         }
     };
+    Ok(tree)
+}
+
+pub fn svg_to_bitmap(svg_data: &str, width: u32, height: u32) -> Result<Vec<Vec<bool>>> {
+    svg_to_bitmap_scaled(svg_data, width, height, 1)
+}
+
+/// Like svg_to_bitmap, with `scale` by `scale` pixels for every pixel of the `width` by `height` canvas
+pub fn svg_to_bitmap_scaled(svg_data: &str, width: u32, height: u32, scale: u32) -> Result<Vec<Vec<bool>>> {
+    let tree = parse_svg(svg_data, width, height)?;
 
-    let mut pixmap = Pixmap::new(width, height).unwrap();
-    render(&tree, usvg::Transform::default(), &mut pixmap.as_mut());
+    // Zero sized, or too big to allocate
+    let mut pixmap = width
+        .checked_mul(scale)
+        .zip(height.checked_mul(scale))
+        .and_then(|(scaled_width, scaled_height)| Pixmap::new(scaled_width, scaled_height))
+        .ok_or_else(|| anyhow::anyhow!("Can't make a {}x{} bitmap at {}x scale", width, height, scale))?;
+    render(&tree, usvg::Transform::from_scale(scale as f32, scale as f32), &mut pixmap.as_mut());
 
     let bitmap = pixmap
         .pixels()
-        .chunks(width as usize)
+        .chunks(pixmap.width() as usize)
         .map(|row| row.iter().map(|p| p.alpha() > 128).collect())
         .collect();
 
@@ -0,0 +1,44 @@
+use log::debug;
+use serde_json::Value as json;
+use std::path::Path;
+
+const XOCHITL_DATA_DIR: &str = "/home/root/.local/share/remarkable/xochitl";
+
+pub struct OpenDocument {
+    pub name: String,
+    /// 1-based page number, if xochitl recorded one
+    pub page: Option<u64>,
+}
+
+/// Guess the document on screen: xochitl stamps `lastOpened` into a document's
+/// metadata when it is opened, so the most recent one is most likely the current one
+pub fn current_document() -> Option<OpenDocument> {
+    let entries = std::fs::read_dir(Path::new(XOCHITL_DATA_DIR)).ok()?;
+
+    let mut latest: Option<(u64, json)> = None;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("metadata") {
+            continue;
+        }
+        let Some(metadata) = std::fs::read_to_string(&path).ok().and_then(|raw| serde_json::from_str::<json>(&raw).ok()) else {
+            continue;
+        };
+        if metadata["type"] != "DocumentType" || metadata["deleted"] == true {
+            continue;
+        }
+        // lastOpened is stored as a string of milliseconds
+        let last_opened = metadata["lastOpened"].as_str().and_then(|v| v.parse::<u64>().ok()).unwrap_or(0);
+        if latest.as_ref().is_none_or(|(latest_opened, _)| last_opened > *latest_opened) {
+            latest = Some((last_opened, metadata));
+        }
+    }
+
+    let (_, metadata) = latest?;
+    let document = OpenDocument {
+        name: metadata["visibleName"].as_str().unwrap_or("Untitled").to_string(),
+        page: metadata["lastOpenedPage"].as_u64().map(|page| page + 1),
+    };
+    debug!("Current document: {} page {:?}", document.name, document.page);
+    Some(document)
+}
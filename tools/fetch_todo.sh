@@ -0,0 +1,10 @@
+#!/bin/sh
+# Example external_command tool, see prompts/tool_fetch_todo.json
+#
+# The model's arguments arrive as JSON on stdin (and as GHOSTWRITER_ARG_* env vars).
+# Whatever we print to stdout becomes the tool result.
+#
+# To read a different file, set TODO_FILE and add it to tool_keep_env in ~/.ghostwriter.toml;
+# everything not listed there is scrubbed from the environment.
+
+cat "${TODO_FILE:-$HOME/todo.txt}" 2>/dev/null || echo "The TODO list is empty"